@@ -0,0 +1,87 @@
+use crate::vec3::Color;
+
+//samples every pixel gets before it can be found converged
+pub const ADAPTIVE_MIN_SAMPLES: u32 = 8;
+
+//running mean and variance of the samples of a pixel, using Welford's algorithm on their luminance
+#[derive(Debug, Clone, Copy)]
+pub struct PixelStats {
+    count: u32,
+    sum: Color,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub fn new() -> PixelStats {
+        PixelStats { count: 0, sum: Color::BLACK, mean: 0.0, m2: 0.0 }
+    }
+
+    pub fn add(&mut self, color: Color) {
+        let (r, g, b) = color.get_components();
+        let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        self.count += 1;
+        self.sum += color;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Color {
+        self.sum / self.count.max(1) as f64
+    }
+
+    //sample variance of the luminance
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {0.0} else {self.m2 / (self.count - 1) as f64}
+    }
+
+    //standard error of the mean luminance after the gamma 2 of the output, where noise shows.
+    //the square root changes by dx / (2 sqrt(x)) for a change dx
+    pub fn error(&self) -> f64 {
+        let standard_error = (self.variance() / self.count as f64).sqrt();
+        standard_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+
+    //whether the pixel has enough samples for its error to be below threshold
+    pub fn converged(&self, threshold: f64) -> bool {
+        self.count >= ADAPTIVE_MIN_SAMPLES && self.error() < threshold
+    }
+}
+
+//color of a pixel that took count samples out of max, from blue for few to red for many
+pub fn heat_color(count: u32, max: u32) -> Color {
+    let t = (count as f64 / max.max(1) as f64).clamp(0.0, 1.0);
+    if t < 0.5 {
+        Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_mean_and_variance() {
+        let mut stats = PixelStats::new();
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            stats.add(value * Color::WHITE);
+        }
+        assert_eq!(stats.count(), 4);
+        assert!((stats.mean() - 2.5 * Color::WHITE).length() < 1e-12);
+        assert!((stats.variance() - 5.0 / 3.0).abs() < 1e-12);
+        assert!(!stats.converged(0.01));
+
+        let mut flat = PixelStats::new();
+        for _ in 0..ADAPTIVE_MIN_SAMPLES {
+            flat.add(Color::WHITE);
+        }
+        assert!(flat.converged(0.01));
+    }
+}
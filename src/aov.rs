@@ -0,0 +1,290 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use raytracer::INFINITY;
+use crate::ray::Ray;
+use crate::hittable::Hittable;
+use crate::material::albedo;
+use crate::vec3::{Vec3, Color};
+use crate::sampler::Sampler;
+use crate::integrator::cosine_direction;
+use crate::scene::Scene;
+use crate::film::{to_fixed, from_fixed};
+
+//auxiliary values of the first surface a camera ray hits, zero where it hits nothing
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    pub albedo: Color,
+    //shading normal, facing the camera
+    pub normal: Vec3,
+    //ray parameter of the hit, which is the distance along rays from Camera::get_ray times their length
+    pub depth: f64,
+    pub position: Vec3,
+    //index of the object in the world plus one
+    pub object_id: u32,
+    //same for materials that are the same
+    pub material_id: u32,
+    //1 if a random direction above the surface is open within the ambient occlusion distance
+    pub occlusion: f64,
+}
+
+pub fn first_hit(r: &Ray, scene: &Scene, ao_distance: f64, sampler: &mut dyn Sampler) -> AovSample {
+    let (index, rec) = match scene.world.hit_object(r, 0.001, INFINITY) {
+        Some(hit) => hit,
+        None => return AovSample {
+            albedo: Color::BLACK,
+            normal: Vec3::origin(),
+            depth: 0.0,
+            position: Vec3::origin(),
+            object_id: 0,
+            material_id: 0,
+            occlusion: 1.0,
+        },
+    };
+
+    let occlusion_ray = Ray::new(rec.point, cosine_direction(rec.normal, sampler.get_2d()));
+    let occluded = scene.world.hit(&occlusion_ray, 0.001, ao_distance).is_some();
+
+    AovSample {
+        albedo: albedo(rec.material),
+        normal: rec.normal,
+        depth: rec.t,
+        position: rec.point,
+        object_id: index as u32 + 1,
+        material_id: scene.world.material_id(index),
+        occlusion: if occluded {0.0} else {1.0},
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AovPass {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    ObjectId,
+    MaterialId,
+    AmbientOcclusion,
+}
+
+impl AovPass {
+    pub const ALL: [AovPass; 7] = [
+        AovPass::Albedo,
+        AovPass::Normal,
+        AovPass::Depth,
+        AovPass::Position,
+        AovPass::ObjectId,
+        AovPass::MaterialId,
+        AovPass::AmbientOcclusion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AovPass::Albedo => "albedo",
+            AovPass::Normal => "normal",
+            AovPass::Depth => "depth",
+            AovPass::Position => "position",
+            AovPass::ObjectId => "object_id",
+            AovPass::MaterialId => "material_id",
+            AovPass::AmbientOcclusion => "ao",
+        }
+    }
+}
+
+//aov passes of the image, summed over the samples of each pixel, which are counted as pixels can
+//take different numbers of them. sums are kept in fixed point like those of Film, so they don't depend
+//on the order the threads' passes are added in. ids aren't averaged and come from the sample of each
+//pixel with the smallest index
+pub struct Aovs {
+    width: u32,
+    height: u32,
+    albedo: Vec<[i64; 3]>,
+    normal: Vec<[i64; 3]>,
+    depth: Vec<i64>,
+    position: Vec<[i64; 3]>,
+    occlusion: Vec<i64>,
+    //sample index, object id and material id
+    ids: Vec<Option<(u32, u32, u32)>>,
+    samples: Vec<u32>,
+}
+
+impl Aovs {
+    pub fn new(width: u32, height: u32) -> Aovs {
+        let n = (width * height) as usize;
+        Aovs {
+            width,
+            height,
+            albedo: vec![[0; 3]; n],
+            normal: vec![[0; 3]; n],
+            depth: vec![0; n],
+            position: vec![[0; 3]; n],
+            occlusion: vec![0; n],
+            ids: vec![None; n],
+            samples: vec![0; n],
+        }
+    }
+
+    //adds sample index of pixel i, j counted from the lower left corner
+    pub fn add_sample(&mut self, i: u32, j: u32, index: u32, sample: &AovSample) {
+        let k = (j * self.width + i) as usize;
+        add_vector(&mut self.albedo[k], vector_to_fixed(sample.albedo));
+        add_vector(&mut self.normal[k], vector_to_fixed(sample.normal));
+        self.depth[k] = self.depth[k].wrapping_add(to_fixed(sample.depth));
+        add_vector(&mut self.position[k], vector_to_fixed(sample.position));
+        self.occlusion[k] = self.occlusion[k].wrapping_add(to_fixed(sample.occlusion));
+        self.ids[k] = earliest(self.ids[k], Some((index, sample.object_id, sample.material_id)));
+        self.samples[k] += 1;
+    }
+
+    pub fn add(&mut self, other: &Aovs) {
+        for k in 0..self.ids.len() {
+            add_vector(&mut self.albedo[k], other.albedo[k]);
+            add_vector(&mut self.normal[k], other.normal[k]);
+            self.depth[k] = self.depth[k].wrapping_add(other.depth[k]);
+            add_vector(&mut self.position[k], other.position[k]);
+            self.occlusion[k] = self.occlusion[k].wrapping_add(other.occlusion[k]);
+            self.ids[k] = earliest(self.ids[k], other.ids[k]);
+            self.samples[k] += other.samples[k];
+        }
+    }
+
+    //value of pass at pixel i, j counted from the lower left corner, averaged over its samples
+    pub fn value(&self, pass: AovPass, i: u32, j: u32) -> Color {
+        let k = (j * self.width + i) as usize;
+        let scale = 1.0 / self.samples[k].max(1) as f64;
+        let (_, object_id, material_id) = self.ids[k].unwrap_or((0, 0, 0));
+        match pass {
+            AovPass::Albedo => scale * vector_from_fixed(self.albedo[k]),
+            AovPass::Normal => scale * vector_from_fixed(self.normal[k]),
+            AovPass::Depth => scale * from_fixed(self.depth[k]) * Color::WHITE,
+            AovPass::Position => scale * vector_from_fixed(self.position[k]),
+            AovPass::ObjectId => object_id as f64 * Color::WHITE,
+            AovPass::MaterialId => material_id as f64 * Color::WHITE,
+            AovPass::AmbientOcclusion => scale * from_fixed(self.occlusion[k]) * Color::WHITE,
+        }
+    }
+
+    //writes every pass as a float image next to the beauty pass at output, as image_albedo.pfm and so on
+    pub fn write(&self, output: &Path) -> io::Result<()> {
+        for pass in AovPass::ALL {
+            let pixels = (0..self.height).flat_map(|j| (0..self.width).map(move |i| (i, j)));
+            let values = pixels.map(|(i, j)| self.value(pass, i, j));
+            write_pfm(&pass_path(output, pass.name()), self.width, self.height, values)?;
+        }
+        Ok(())
+    }
+}
+
+fn vector_to_fixed(v: Vec3) -> [i64; 3] {
+    [to_fixed(v.x()), to_fixed(v.y()), to_fixed(v.z())]
+}
+
+fn vector_from_fixed([x, y, z]: [i64; 3]) -> Vec3 {
+    Vec3::new(from_fixed(x), from_fixed(y), from_fixed(z))
+}
+
+fn add_vector(sum: &mut [i64; 3], value: [i64; 3]) {
+    for (sum, value) in sum.iter_mut().zip(value) {
+        *sum = sum.wrapping_add(value);
+    }
+}
+
+//ids of whichever sample came first
+fn earliest(a: Option<(u32, u32, u32)>, b: Option<(u32, u32, u32)>) -> Option<(u32, u32, u32)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.0 < a.0 {b} else {a}),
+        _ => a.or(b),
+    }
+}
+
+//path of pass name next to output
+fn pass_path(output: &Path, name: &str) -> PathBuf {
+    let stem = output.file_stem().and_then(|stem| stem.to_str()).unwrap_or("image");
+    output.with_file_name(format!("{}_{}.pfm", stem, name))
+}
+
+//portable float map, rows go from the bottom of the image to the top
+fn write_pfm(path: &Path, width: u32, height: u32, pixels: impl Iterator<Item = Color>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for color in pixels {
+        for value in [color.x(), color.y(), color.z()] {
+            file.write_all(&(value as f32).to_le_bytes())?;
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::Material;
+    use crate::light_sampler::{LightList, LightSelection};
+    use crate::sky::Sky;
+    use crate::sampler::IndependentSampler;
+
+    #[test]
+    fn aovs_of_first_hit() {
+        let material = Material::Lambertian { albedo: Color::new(0.2, 0.4, 0.6) };
+        let list: Vec<Box<dyn Hittable + Send + Sync>> = vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 100.0), 1.0, Material::Lambertian { albedo: Color::WHITE })),
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 5.0), 1.0, material)),
+            Box::new(Sphere::new(Vec3::new(100.0, 0.0, 0.0), 1.0, Material::Lambertian { albedo: Color::WHITE })),
+        ];
+        let scene = Scene { world: HittableList::new(list), lights: LightList::new(Vec::new(), LightSelection::Uniform), analytic_lights: Vec::new(), fog: None, sky: Sky::Gradient, spectral: false };
+
+        let sample = first_hit(&Ray::new(Vec3::origin(), Vec3::new(0.0, 0.0, 1.0)), &scene, 1.0, &mut IndependentSampler);
+        assert_eq!(sample.albedo, Color::new(0.2, 0.4, 0.6));
+        assert_eq!(sample.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(sample.depth, 4.0);
+        assert_eq!(sample.position, Vec3::new(0.0, 0.0, 4.0));
+        assert_eq!(sample.object_id, 2);
+        //materials are numbered as they first appear, the same white is one material
+        assert_eq!(sample.material_id, 2);
+        assert_eq!(scene.world.material_id(0), 1);
+        assert_eq!(scene.world.material_id(2), 1);
+        assert_eq!(sample.occlusion, 1.0);
+
+        let miss = first_hit(&Ray::new(Vec3::origin(), Vec3::new(0.0, 1.0, 0.0)), &scene, 1.0, &mut IndependentSampler);
+        assert_eq!(miss.object_id, 0);
+    }
+
+    #[test]
+    fn aovs_merge_in_any_order() {
+        let sample = |index: u32| AovSample {
+            albedo: Color::new(0.1 * index as f64, 0.3, 1.0 / 3.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            depth: 1e3 + 0.1 * index as f64,
+            position: Vec3::new(-7.3, 0.2 * index as f64, 1e4),
+            object_id: index + 1,
+            material_id: 10 + index,
+            occlusion: (index % 2) as f64,
+        };
+        //the samples split between two threads, merged one way and the other
+        let mut first = Aovs::new(1, 1);
+        let mut second = Aovs::new(1, 1);
+        for index in [3, 0, 4] {
+            first.add_sample(0, 0, index, &sample(index));
+        }
+        for index in [2, 1] {
+            second.add_sample(0, 0, index, &sample(index));
+        }
+        let mut forward = Aovs::new(1, 1);
+        forward.add(&first);
+        forward.add(&second);
+        let mut backward = Aovs::new(1, 1);
+        backward.add(&second);
+        backward.add(&first);
+
+        for pass in AovPass::ALL {
+            assert_eq!(forward.value(pass, 0, 0), backward.value(pass, 0, 0));
+        }
+        assert_eq!(forward.value(AovPass::ObjectId, 0, 0), Color::WHITE);
+        assert_eq!(forward.value(AovPass::MaterialId, 0, 0), 10.0 * Color::WHITE);
+        assert!((forward.value(AovPass::Depth, 0, 0).x() - 1000.2).abs() < 1e-6);
+        assert!((forward.value(AovPass::AmbientOcclusion, 0, 0).x() - 0.4).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,484 @@
+use raytracer::{INFINITY, PI};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::material::{scatter, emitted, evaluate, is_specular_sample};
+use crate::vec3::{Vec3, Color, Point3, dot, unit_vector};
+use crate::spectrum::SampledWavelengths;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::integrator::{Integrator, analytic_lights, sky_radiance, cosine_direction, transmittance, ROULETTE_DEPTH, MAX_WALK_STEPS, SKY_DISTANCE};
+
+//bidirectional path tracer, every vertex of a path from the camera is connected to every vertex of
+//a path leaving an emitter and the strategies are weighted against each other with the power heuristic.
+//light paths only start on emitters in the world, the sky and the analytic lights are reached from the camera alone
+pub struct BdptIntegrator {
+    pub max_depth: u32,
+    pub camera: Camera,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Copy, Clone)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    //where the vertex is, with the normal facing the side the path is on
+    rec: HitRecord<'a>,
+    //ray the path arrived at a surface with
+    r_in: Ray,
+    //throughput of the path up to here, leaving out the weight of a dispersive interface
+    beta: Color,
+    //the path went through a dispersive interface and only carries its own wavelength
+    dispersed: bool,
+    //the path left through a lobe holding a single direction, which no other strategy can sample
+    delta: bool,
+    //the path scattered in a medium on its way from the previous vertex, so the two can't be connected
+    walked: bool,
+    //area densities of the vertex being chosen from the camera's side and from the light's side
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(r: &Ray) -> Vertex<'a> {
+        let mut rec = HitRecord::default();
+        rec.point = r.origin;
+        Vertex { kind: VertexKind::Camera, rec, r_in: *r, beta: Color::WHITE, dispersed: false, delta: false, walked: false, pdf_fwd: 0.0, pdf_rev: 0.0 }
+    }
+
+    fn point(&self) -> Point3 {
+        self.rec.point
+    }
+
+    //density with respect to area at next of a direction from here chosen with solid angle density pdf_dir
+    fn convert(&self, pdf_dir: f64, next: &Vertex) -> f64 {
+        let w = next.point() - self.point();
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0
+        }
+        let pdf = pdf_dir / distance_squared;
+        //points in a volume have no surface to be foreshortened
+        if next.kind == VertexKind::Camera || next.rec.material.is_volume() {
+            pdf
+        } else {
+            pdf * dot(next.rec.normal, w).abs() / distance_squared.sqrt()
+        }
+    }
+
+    //light can be carried from here towards a vertex of the other path
+    fn connectible(&self) -> bool {
+        self.kind != VertexKind::Surface || !self.rec.material.is_specular()
+    }
+
+    //area density of the path continuing from here to next, having arrived from prev
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = next.point() - self.point();
+        let pdf_dir = match self.kind {
+            VertexKind::Camera => camera.pdf_dir(self.point(), direction),
+            VertexKind::Light => self.emission_pdf(direction),
+            VertexKind::Surface => match prev {
+                Some(prev) => {
+                    let r_in = self.r_in.bounce(prev.point(), self.point() - prev.point());
+                    evaluate(self.rec.material, &r_in, &facing(&self.rec, &r_in), direction).1
+                }
+                None => 0.0,
+            },
+        };
+        self.convert(pdf_dir, next)
+    }
+
+    //emitters send light out cosine weighted from the side the normal is on
+    fn emission_pdf(&self, direction: Vec3) -> f64 {
+        dot(unit_vector(direction), self.rec.normal).max(0.0) / PI
+    }
+
+    //light leaving towards point per unit of throughput, times the cosine there
+    fn f(&self, point: Point3, lambda: &SampledWavelengths) -> Color {
+        let direction = point - self.point();
+        match self.kind {
+            VertexKind::Light => emitted(self.rec.material, lambda) * PI * self.emission_pdf(direction),
+            VertexKind::Surface => lambda.reflectance(evaluate(self.rec.material, &self.r_in, &self.rec, direction).0),
+            VertexKind::Camera => Color::BLACK,
+        }
+    }
+}
+
+//rec with its normal turned towards the side r_in arrives from
+fn facing<'a>(rec: &HitRecord<'a>, r_in: &Ray) -> HitRecord<'a> {
+    let outward_normal = if rec.front_face {rec.normal} else {-rec.normal};
+    let mut rec = *rec;
+    rec.set_face_normal(r_in, outward_normal);
+    rec
+}
+
+//weight of light that went through a dispersive interface on either part of the path
+fn dispersion(dispersed: bool, lambda: &SampledWavelengths) -> Color {
+    if dispersed {lambda.collapse()} else {Color::WHITE}
+}
+
+//extends path from ray r until it is absorbed or holds max_vertices, pdf_dir is the solid angle density
+//the last vertex chose r with. Returns the ray and throughput the path left the scene with, if it did
+fn random_walk<'a>(
+        scene: &'a Scene,
+        r: Ray,
+        beta: Color,
+        pdf_dir: f64,
+        sampler: &mut dyn Sampler,
+        max_vertices: usize,
+        path: &mut Vec<Vertex<'a>>
+    ) -> Option<(Ray, Color)> {
+
+    let lambda = &SampledWavelengths::new(r.wavelength, scene.spectral);
+    let mut r = r;
+    let mut beta = beta;
+    let mut pdf_dir = pdf_dir;
+
+    while path.len() < max_vertices {
+
+        //scattering in media only moves the path along, the vertices it creates aren't stored
+        let mut steps = 0;
+        let op_rec = loop {
+            let op_rec = scene.world.hit(&r, 0.001, INFINITY);
+            let medium = match r.medium.or(scene.fog) {
+                Some(medium) => medium.sampled(lambda),
+                None => break op_rec,
+            };
+
+            let length = r.direction.length();
+            let distance_max = op_rec.as_ref().map_or(SKY_DISTANCE, |rec| rec.t * length);
+            let (distance, weight) = medium.sample_distance(distance_max, sampler.get_2d());
+            beta = beta * weight;
+            match distance {
+                Some(distance) => {
+                    steps += 1;
+                    if steps > MAX_WALK_STEPS {return None;}
+                    r = r.bounce(r.at(distance / length), medium.sample_phase(r.direction, sampler.get_2d()));
+                }
+                None => break op_rec,
+            }
+        };
+
+        let rec = match op_rec {
+            Some(rec) => rec,
+            None => return Some((r, beta)),
+        };
+
+        let prev = path.len() - 1;
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            rec,
+            r_in: r,
+            beta,
+            dispersed: r.dispersed,
+            delta: false,
+            walked: steps > 0,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        if !vertex.walked {
+            vertex.pdf_fwd = path[prev].convert(pdf_dir, &vertex);
+        }
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let mut attenuation = Color::BLACK;
+        let mut r_scattered = Ray::new(Vec3::origin(), Vec3::origin());
+        if !scatter(rec.material, &r, &rec, &mut attenuation, &mut r_scattered, sampler) {
+            break;
+        }
+        if rec.material.is_dispersive() && !r.dispersed {
+            r_scattered.dispersed = true;
+        }
+        vertex.delta = is_specular_sample(rec.material, &r, &rec, r_scattered.direction);
+        path[prev + 1].delta = vertex.delta;
+
+        //densities of scattering towards the next vertex and, for the light's side, back to the previous one
+        let (pdf_next, pdf_back) = if vertex.delta {
+            (0.0, 0.0)
+        } else {
+            let r_back = r.bounce(rec.point + r_scattered.direction, -r_scattered.direction);
+            (evaluate(rec.material, &r, &rec, r_scattered.direction).1, evaluate(rec.material, &r_back, &facing(&rec, &r_back), -r.direction).1)
+        };
+        if !vertex.walked {
+            path[prev].pdf_rev = vertex.convert(pdf_back, &path[prev]);
+        }
+
+        beta = beta * lambda.reflectance(attenuation);
+        pdf_dir = pdf_next;
+        r = r_scattered;
+
+        //paths carrying little light are ended at random, the survivors make up for them
+        if path.len() > ROULETTE_DEPTH as usize {
+            let (x, y, z) = beta.get_components();
+            let survival = x.max(y).max(z).min(0.95);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            beta = beta / survival;
+        }
+    }
+
+    None
+}
+
+//film coordinates a connection to the lens lands at and the vertex on the lens
+type LensSplat<'a> = (f64, f64, Vertex<'a>);
+
+impl BdptIntegrator {
+    //light arriving at the camera along the path made of the first t camera and the first s light vertices,
+    //for t >= 2. connections to the lens go through connect_lens
+    fn connect(
+            &self,
+            scene: &Scene,
+            lambda: &SampledWavelengths,
+            camera_path: &[Vertex],
+            light_path: &[Vertex],
+            s: usize,
+            t: usize
+        ) -> Option<Color> {
+
+        let pt = &camera_path[t - 1];
+        if s == 0 {
+            let emission = emitted(pt.rec.material, lambda);
+            return if emission == Color::BLACK {None} else {Some(pt.beta * emission * dispersion(pt.dispersed, lambda))}
+        }
+
+        let qs = &light_path[s - 1];
+        if !qs.connectible() || !pt.connectible() {
+            return None
+        }
+        let f = qs.beta * qs.f(pt.point(), lambda) * pt.f(qs.point(), lambda) * pt.beta;
+        if f == Color::BLACK {
+            return None
+        }
+        let distance_squared = (pt.point() - qs.point()).length_squared();
+        let visibility = visibility(scene, lambda, &pt.r_in, pt.point(), qs.point());
+        Some(f * visibility * dispersion(pt.dispersed || qs.dispersed, lambda) / distance_squared)
+    }
+
+    //light arriving at a point on the lens taken from sampler from the light vertex qs, with the film
+    //coordinates it lands at and the vertex on the lens
+    fn connect_lens<'a>(
+            &self,
+            scene: &Scene,
+            lambda: &SampledWavelengths,
+            qs: &Vertex<'a>,
+            sampler: &mut dyn Sampler
+        ) -> Option<(Color, LensSplat<'a>)> {
+
+        if !qs.connectible() {
+            return None
+        }
+        let sample = self.camera.sample_towards(qs.point(), sampler)?;
+        let visibility = visibility(scene, lambda, &qs.r_in, qs.point(), sample.lens);
+        let l = qs.beta * qs.f(sample.lens, lambda) * sample.weight * visibility * dispersion(qs.dispersed, lambda);
+        let lens = Vertex::camera(&Ray::new(sample.lens, qs.point() - sample.lens));
+        if l == Color::BLACK {None} else {Some((l, (sample.u, sample.v, lens)))}
+    }
+
+    //power heuristic weight of the strategy with s light and t camera vertices among all that make the same path
+    fn mis_weight(&self, scene: &Scene, camera_path: &[Vertex], light_path: &[Vertex], s: usize, t: usize, lens: Option<Vertex>) -> f64 {
+        if s + t == 2 {
+            return 1.0
+        }
+
+        let mut camera_path = camera_path[..t].to_vec();
+        let mut light_path = light_path[..s].to_vec();
+        if let Some(lens) = lens {
+            camera_path[0] = lens;
+        }
+
+        //densities around the connection of this strategy
+        let pt = camera_path[t - 1];
+        let pt_minus = t.checked_sub(2).map(|i| camera_path[i]);
+        if s > 0 {
+            let qs = light_path[s - 1];
+            let qs_minus = s.checked_sub(2).map(|i| light_path[i]);
+            camera_path[t - 1].pdf_rev = qs.pdf(&self.camera, qs_minus.as_ref(), &pt);
+            if let (Some(pt_minus), false) = (pt_minus, pt.walked) {
+                camera_path[t - 2].pdf_rev = pt.pdf(&self.camera, Some(&qs), &pt_minus);
+            }
+            light_path[s - 1].pdf_rev = pt.pdf(&self.camera, pt_minus.as_ref(), &qs);
+            if let (Some(qs_minus), false) = (qs_minus, qs.walked) {
+                light_path[s - 2].pdf_rev = qs.pdf(&self.camera, Some(&pt), &qs_minus);
+            }
+            light_path[s - 1].delta = false;
+        } else if let Some(pt_minus) = pt_minus {
+            //the camera found an emitter, which a light path could have started on
+            camera_path[t - 1].pdf_rev = scene.lights.surface_pdf(pt_minus.point(), pt.point() - pt_minus.point(), pt.point());
+            if !pt.walked {
+                camera_path[t - 2].pdf_rev = pt.convert(pt.emission_pdf(pt_minus.point() - pt.point()), &pt_minus);
+            }
+        }
+        camera_path[t - 1].delta = false;
+
+        let ratio = |pdf_rev: f64, pdf_fwd: f64| {
+            let remap = |pdf: f64| if pdf == 0.0 {1.0} else {pdf};
+            let r = remap(pdf_rev) / remap(pdf_fwd);
+            r * r
+        };
+
+        //relative densities of moving the connection towards the camera, the lens itself is never hit
+        let mut sum = 0.0;
+        let mut r = 1.0;
+        for i in (1..t).rev() {
+            r *= ratio(camera_path[i].pdf_rev, camera_path[i].pdf_fwd);
+            if !camera_path[i].delta && !camera_path[i - 1].delta && !camera_path[i].walked {
+                sum += r;
+            }
+        }
+
+        //and towards the light
+        let mut r = 1.0;
+        for i in (0..s).rev() {
+            r *= ratio(light_path[i].pdf_rev, light_path[i].pdf_fwd);
+            let previous_delta = i > 0 && light_path[i - 1].delta;
+            if !light_path[i].delta && !previous_delta && !light_path[i].walked {
+                sum += r;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+}
+
+//fraction of light passing from one point to another, zero if something is in between
+fn visibility(scene: &Scene, lambda: &SampledWavelengths, r: &Ray, from: Point3, to: Point3) -> Color {
+    let distance = (to - from).length();
+    let mut shadow_ray = r.bounce(from, (to - from) / distance);
+    shadow_ray.medium = None;
+    if scene.world.hit(&shadow_ray, 0.001, distance - 0.001).is_some() {
+        return Color::BLACK
+    }
+    transmittance(&shadow_ray, scene, lambda, distance)
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, _pixel: (u32, u32), _index: u32, film: &mut Film) -> Color {
+        let lambda = SampledWavelengths::new(r.wavelength, scene.spectral);
+        let mut radiance = Color::BLACK;
+
+        let mut camera_path = vec![Vertex::camera(r)];
+        let pdf_dir = self.camera.pdf_dir(r.origin, r.direction);
+        if let Some((r_out, beta)) = random_walk(scene, *r, Color::WHITE, pdf_dir, sampler, self.max_depth as usize + 2, &mut camera_path) {
+            //the sun was sampled at the last vertex if the path left straight from it through a lobe that isn't specular
+            let last = camera_path[camera_path.len() - 1];
+            let bsdf_pdf = if last.kind == VertexKind::Surface && !last.delta && r_out.origin == last.point() {
+                Some(evaluate(last.rec.material, &last.r_in, &last.rec, r_out.direction).1)
+            } else {
+                None
+            };
+            radiance += beta * sky_radiance(&r_out, scene, &lambda, bsdf_pdf) * dispersion(r_out.dispersed, &lambda);
+        }
+
+        //the analytic lights can't be hit, they are only reached by sampling them directly
+        for vertex in &camera_path[1..] {
+            if vertex.connectible() {
+                radiance += vertex.beta * analytic_lights(&vertex.r_in, &vertex.rec, scene, &lambda, sampler) * dispersion(vertex.dispersed, &lambda);
+            }
+        }
+
+        let mut light_path = Vec::new();
+        if let Some((rec, pdf_pos)) = scene.lights.sample_surface(sampler) {
+            let mut r_light = r.bounce(rec.point, cosine_direction(rec.normal, sampler.get_2d()));
+            r_light.medium = None;
+            let light = Vertex { kind: VertexKind::Light, rec, r_in: r_light, beta: Color::WHITE / pdf_pos, dispersed: false, delta: false, walked: false, pdf_fwd: pdf_pos, pdf_rev: 0.0 };
+            light_path.push(light);
+
+            //the cosine of the emission cancels with that of its density
+            let pdf_dir = light.emission_pdf(r_light.direction);
+            let beta = emitted(rec.material, &lambda) * PI / pdf_pos;
+            random_walk(scene, r_light, beta, pdf_dir, sampler, self.max_depth as usize + 1, &mut light_path);
+        }
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > self.max_depth as usize {
+                    continue;
+                }
+                if t == 1 {
+                    if let Some((l, (u, v, lens))) = self.connect_lens(scene, &lambda, &light_path[s - 1], sampler) {
+                        let weight = self.mis_weight(scene, &camera_path, &light_path, s, t, Some(lens));
+                        film.splat(u, v, lambda.to_rgb(l * weight));
+                    }
+                } else if let Some(l) = self.connect(scene, &lambda, &camera_path, &light_path, s, t) {
+                    radiance += l * self.mis_weight(scene, &camera_path, &light_path, s, t, None);
+                }
+            }
+        }
+
+        lambda.to_rgb(radiance)
+    }
+
+    fn splats(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::light_sampler::{LightList, LightSelection};
+    use crate::material::Material;
+    use crate::rectangle::{RectangleXY, RectangleXZ};
+    use crate::sphere::Sphere;
+    use crate::sampler::IndependentSampler;
+    use crate::integrator::PathIntegrator;
+    use crate::sky::Sky;
+    use crate::spectrum::Spectrum;
+
+    //mean pixel of a small image of a corner lit by a panel, with light paths splatted.
+    //a black sphere around it keeps out the sky
+    fn render(integrator: &dyn Integrator, camera: &Camera) -> Color {
+        let floor = RectangleXZ::new(-5.0, 5.0, -5.0, 5.0, 0.0, Material::Lambertian { albedo: Color::new(0.7, 0.6, 0.5) });
+        let wall = RectangleXY::new(-5.0, 5.0, 0.0, 5.0, -1.0, Material::Lambertian { albedo: Color::new(0.5, 0.7, 0.8) });
+        let panel = RectangleXZ::new(-0.5, 0.5, -0.5, 0.5, 1.0, Material::DiffuseLight { emission: Spectrum::Rgb(Color::new(4.0, 4.0, 4.0)) });
+        let dome = Sphere::new(Point3::origin(), 20.0, Material::Lambertian { albedo: Color::BLACK });
+        let scene = Scene {
+            world: HittableList::new(vec![Box::new(floor), Box::new(wall), Box::new(panel.clone()), Box::new(dome)]),
+            lights: LightList::new(vec![Box::new(panel)], LightSelection::Uniform),
+            analytic_lights: Vec::new(),
+            fog: None,
+            sky: Sky::Gradient,
+            spectral: false,
+        };
+
+        let (n, samples) = (8, 512);
+        let mut film = Film::new(n, n);
+        let mut sampler = IndependentSampler;
+        let mut sum = Color::BLACK;
+        for j in 0..n {
+            for i in 0..n {
+                for _ in 0..samples {
+                    let u = (i as f64 + raytracer::random_f64()) / n as f64;
+                    let v = (j as f64 + raytracer::random_f64()) / n as f64;
+                    sum += integrator.li(&camera.get_ray(u, v, &mut sampler), &scene, &mut sampler, (0, 0), 0, &mut film);
+                }
+            }
+        }
+        for j in 0..n {
+            for i in 0..n {
+                sum += film.pixel(i, j);
+            }
+        }
+        sum / (n * n * samples) as f64
+    }
+
+    #[test]
+    fn matches_path_tracing() {
+        let camera = Camera::new(Point3::new(0.0, 3.0, 3.0), Point3::origin(), Vec3::new(0.0, 1.0, 0.0), 60.0, 1.0, 0.0, 1.0);
+        let bdpt = render(&BdptIntegrator { max_depth: 8, camera }, &camera);
+        let path = render(&PathIntegrator { max_depth: 8 }, &camera);
+        assert!((bdpt - path).length() / path.length() < 0.03, "{:?} {:?}", bdpt, path);
+    }
+}
@@ -1,10 +1,13 @@
 use raytracer::deg_to_rad;
 
 use crate::{ASPECT_RATIO};
-use crate::vec3::{Vec3, Point3, unit_vector, cross};
+use crate::vec3::{Vec3, Point3, unit_vector, cross, dot};
 use crate::ray::Ray;
+use crate::spectrum::{LAMBDA_MIN, LAMBDA_MAX};
+use crate::sampler::Sampler;
 
 #[derive(Clone, Copy)]
+#[allow(dead_code)]
 pub struct Camera {
     vfov: f64,
     
@@ -21,6 +24,19 @@ pub struct Camera {
     vertical: Vec3,
 
     lower_left_corner: Point3,
+    //direction the camera looks in
+    forward: Vec3,
+}
+
+//connection of a point in the scene to the camera, for light tracing
+pub struct CameraSample {
+    //point on the lens
+    pub lens: Point3,
+    //film coordinates as passed to get_ray
+    pub u: f64,
+    pub v: f64,
+    //importance arriving at the point divided by the solid angle density of choosing the lens point
+    pub weight: f64,
 }
 
 impl Camera {
@@ -28,7 +44,6 @@ impl Camera {
 
         let vfov = deg_to_rad(vfov);
         let h = (vfov/2.0).tan();
-        let aspect_ratio = aspect_ratio;
         let viewport_height =  2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
@@ -52,9 +67,11 @@ impl Camera {
             horizontal,
             vertical,
             lower_left_corner,
+            forward: -w,
         }
     }
 
+    #[allow(dead_code)]
     pub fn default() -> Camera {
         let vfov = deg_to_rad(90.0);
         let h = (vfov/2.0).tan();
@@ -89,17 +106,57 @@ impl Camera {
             horizontal,
             vertical,
             lower_left_corner,
+            forward: -w,
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = (self.aperture/2.0) * Vec3::random_in_unit_disk();
+    //ray through film coordinates u, v, with the point on the lens and the wavelength taken from sampler
+    pub fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = (self.aperture/2.0) * Vec3::sample_unit_disk(sampler.get_2d());
         let uu = unit_vector(self.horizontal);
         let vv = unit_vector(self.vertical);
         let offset = uu * rd.x() + vv * rd.y();
         Ray { 
             origin: self.origin + offset,
-            direction: self.lower_left_corner + self.horizontal*u + self.vertical*v - self.origin - offset
+            direction: self.lower_left_corner + self.horizontal*u + self.vertical*v - self.origin - offset,
+            wavelength: LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * sampler.get_1d(),
+            dispersed: false,
+            medium: None,
+        }
+    }
+
+    //film coordinates of the ray leaving lens in direction, None if they are off the film
+    fn film_coordinates(&self, lens: Point3, direction: Vec3) -> Option<(f64, f64)> {
+        let depth = dot(direction, self.forward);
+        if depth <= 0.0 {
+            return None
         }
+        let p = lens + direction * (self.focus_dist / depth);
+        let u = dot(p - self.lower_left_corner, self.horizontal) / self.horizontal.length_squared();
+        let v = dot(p - self.lower_left_corner, self.vertical) / self.vertical.length_squared();
+        if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {Some((u, v))} else {None}
+    }
+
+    //solid angle density of get_ray choosing direction for a ray leaving the lens at origin,
+    //with film coordinates spread uniformly over the viewport
+    pub fn pdf_dir(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self.film_coordinates(origin, direction).is_none() {
+            return 0.0
+        }
+        let cos_theta = dot(unit_vector(direction), self.forward);
+        1.0 / (self.viewport_width * self.viewport_height * cos_theta.powi(3))
+    }
+
+    //point on the lens seeing point, taken from sampler, None if it is outside the view
+    pub fn sample_towards(&self, point: Point3, sampler: &mut dyn Sampler) -> Option<CameraSample> {
+        let rd = (self.aperture/2.0) * Vec3::sample_unit_disk(sampler.get_2d());
+        let lens = self.origin + unit_vector(self.horizontal) * rd.x() + unit_vector(self.vertical) * rd.y();
+        let direction = point - lens;
+        let (u, v) = self.film_coordinates(lens, direction)?;
+
+        //importance 1 / (viewport area * lens area * cos^4) over the density distance^2 / (lens area * cos)
+        let cos_theta = dot(unit_vector(direction), self.forward);
+        let weight = 1.0 / (self.viewport_width * self.viewport_height * cos_theta.powi(3) * direction.length_squared());
+        Some(CameraSample { lens, u, v, weight })
     }
 }
\ No newline at end of file
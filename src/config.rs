@@ -0,0 +1,416 @@
+use crate::light_sampler::LightSelection;
+use crate::sky::SkyModel;
+use crate::integrator::IntegratorKind;
+use crate::sampler::SamplerKind;
+use crate::filter::FilterKind;
+use crate::scene::SceneKind;
+use std::path::PathBuf;
+
+//render settings that can be changed from the command line
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub scene: SceneKind,
+    //density grid (.vol or .raw) for the cloud of the volumes scene, a procedural one is generated if not set
+    pub volume: Option<PathBuf>,
+    //trace light at sampled wavelengths instead of rgb
+    pub spectral: bool,
+    pub integrator: IntegratorKind,
+    pub light_selection: LightSelection,
+    pub sky_model: SkyModel,
+    //position of the sun in degrees, see PreethamSky
+    pub sun_elevation: f64,
+    pub sun_azimuth: f64,
+    pub turbidity: f64,
+    //chance of a Metropolis mutation replacing the whole path instead of perturbing it
+    pub large_step_probability: f64,
+    //write the aov passes next to the image
+    pub aovs: bool,
+    //strength of the denoiser, 0 leaves the image as rendered
+    pub denoise: f64,
+    //also write the image as rendered when it is denoised
+    pub raw: bool,
+    //most samples a pixel can take with adaptive sampling, 0 gives every pixel SAMPLES_PER_PIXEL
+    pub adaptive_max_samples: u32,
+    //error in the output a pixel has to get below to stop taking samples, see PixelStats::error
+    pub noise_threshold: f64,
+    //write an image of the number of samples each pixel took
+    pub heatmap: bool,
+    //where the random numbers of each pixel sample come from
+    pub sampler: SamplerKind,
+    //every random number of the render derives from it, so the same settings give the same image
+    pub seed: u32,
+    //reconstruction filter of the pixels, and its radius in pixels, 0 for the filter's own
+    pub filter: FilterKind,
+    pub filter_radius: f64,
+    //threads rendering tiles, 0 for as many as the machine can run at once
+    pub threads: u32,
+    //render one sample per pixel over the whole image at a time, writing the image so far every
+    //snapshot_passes passes or snapshot_seconds seconds, 0 turns either off. it stops after passes
+    //passes, or with 0 runs until interrupted and the last snapshot is the image
+    pub progressive: bool,
+    pub passes: u32,
+    pub snapshot_passes: u32,
+    pub snapshot_seconds: f64,
+}
+
+impl Config {
+    pub fn default() -> Config {
+        Config {
+            scene: SceneKind::Default,
+            volume: None,
+            spectral: false,
+            integrator: IntegratorKind::Path,
+            light_selection: LightSelection::Bvh,
+            sky_model: SkyModel::Preetham,
+            sun_elevation: 35.0,
+            sun_azimuth: 150.0,
+            turbidity: 3.0,
+            large_step_probability: 0.3,
+            aovs: false,
+            denoise: 0.0,
+            raw: false,
+            adaptive_max_samples: 0,
+            noise_threshold: 0.01,
+            heatmap: false,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            filter: FilterKind::Box,
+            filter_radius: 0.0,
+            threads: 0,
+            progressive: false,
+            passes: 0,
+            snapshot_passes: 0,
+            snapshot_seconds: 30.0,
+        }
+    }
+
+    //parses options of the form --name value, anything not given keeps its default
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--scene" => {
+                    config.scene = match value()?.as_str() {
+                        "default" => SceneKind::Default,
+                        "glass" => SceneKind::Glass,
+                        "materials" => SceneKind::Materials,
+                        "volumes" => SceneKind::Volumes,
+                        "lights" => SceneKind::Lights,
+                        "showcase" => SceneKind::Showcase,
+                        other => return Err(format!("unknown scene {}, expected default, glass, materials, volumes, lights or showcase", other)),
+                    }
+                }
+                "--volume" => {
+                    let path = PathBuf::from(value()?);
+                    if !matches!(path.extension().and_then(|e| e.to_str()), Some("vol") | Some("raw")) {
+                        return Err(format!("expected a .vol or .raw file for {}, got {}", arg, path.display()));
+                    }
+                    if !path.is_file() {
+                        return Err(format!("no file {} for {}", path.display(), arg));
+                    }
+                    config.volume = Some(path);
+                }
+                "--spectral" => config.spectral = switch(&arg, value()?)?,
+                "--integrator" => {
+                    config.integrator = match value()?.as_str() {
+                        "path" => IntegratorKind::Path,
+                        "direct" => IntegratorKind::Direct,
+                        "bdpt" => IntegratorKind::Bidirectional,
+                        "photon" => IntegratorKind::PhotonMapping,
+                        "mlt" => IntegratorKind::Metropolis,
+                        "ao" => IntegratorKind::AmbientOcclusion,
+                        "debug" => IntegratorKind::Debug,
+                        other => return Err(format!("unknown integrator {}, expected path, direct, bdpt, photon, mlt, ao or debug", other)),
+                    }
+                }
+                "--light-sampling" => {
+                    config.light_selection = match value()?.as_str() {
+                        "uniform" => LightSelection::Uniform,
+                        "power" => LightSelection::Power,
+                        "bvh" => LightSelection::Bvh,
+                        other => return Err(format!("unknown light sampling {}, expected uniform, power or bvh", other)),
+                    }
+                }
+                "--sky" => {
+                    config.sky_model = match value()?.as_str() {
+                        "gradient" => SkyModel::Gradient,
+                        "preetham" => SkyModel::Preetham,
+                        other => return Err(format!("unknown sky {}, expected gradient or preetham", other)),
+                    }
+                }
+                "--sun-elevation" => {
+                    config.sun_elevation = number(&arg, value()?)?;
+                    if config.sun_elevation <= 0.0 || config.sun_elevation > 90.0 {
+                        return Err(format!("{} puts the sun below the horizon, expected degrees above 0 up to 90", arg));
+                    }
+                }
+                "--sun-azimuth" => config.sun_azimuth = number(&arg, value()?)?,
+                "--turbidity" => {
+                    config.turbidity = number(&arg, value()?)?;
+                    if !(2.0..=10.0).contains(&config.turbidity) {
+                        return Err(format!("{} is outside the range the sky model was fitted to, expected a value from 2 to 10", arg));
+                    }
+                }
+                "--large-step" => {
+                    config.large_step_probability = number(&arg, value()?)?;
+                    if !(0.0..=1.0).contains(&config.large_step_probability) {
+                        return Err(format!("{} is a probability, expected a value from 0 to 1", arg));
+                    }
+                }
+                "--aovs" => config.aovs = switch(&arg, value()?)?,
+                "--denoise" => {
+                    config.denoise = number(&arg, value()?)?;
+                    if config.denoise < 0.0 {
+                        return Err(format!("{} can't be negative", arg));
+                    }
+                }
+                "--raw" => config.raw = switch(&arg, value()?)?,
+                "--adaptive" => {
+                    let value = value()?;
+                    config.adaptive_max_samples = value.parse().map_err(|_| format!("expected a sample count for {}, got {}", arg, value))?;
+                }
+                "--noise-threshold" => {
+                    config.noise_threshold = number(&arg, value()?)?;
+                    if config.noise_threshold < 0.0 {
+                        return Err(format!("{} can't be negative", arg));
+                    }
+                }
+                "--heatmap" => config.heatmap = switch(&arg, value()?)?,
+                "--sampler" => {
+                    config.sampler = match value()?.as_str() {
+                        "independent" => SamplerKind::Independent,
+                        "stratified" => SamplerKind::Stratified,
+                        "halton" => SamplerKind::Halton,
+                        "sobol" => SamplerKind::Sobol,
+                        "bluenoise" => SamplerKind::BlueNoise,
+                        other => return Err(format!("unknown sampler {}, expected independent, stratified, halton, sobol or bluenoise", other)),
+                    };
+                }
+                "--seed" => {
+                    let value = value()?;
+                    config.seed = value.parse().map_err(|_| format!("expected a whole number for {}, got {}", arg, value))?;
+                }
+                "--filter" => {
+                    config.filter = match value()?.as_str() {
+                        "box" => FilterKind::Box,
+                        "tent" => FilterKind::Tent,
+                        "gaussian" => FilterKind::Gaussian,
+                        "mitchell" => FilterKind::Mitchell,
+                        "lanczos" => FilterKind::Lanczos,
+                        other => return Err(format!("unknown filter {}, expected box, tent, gaussian, mitchell or lanczos", other)),
+                    };
+                }
+                "--filter-radius" => {
+                    config.filter_radius = number(&arg, value()?)?;
+                    if config.filter_radius < 0.0 {
+                        return Err(format!("{} can't be negative", arg));
+                    }
+                }
+                "--threads" => {
+                    let value = value()?;
+                    config.threads = value.parse().map_err(|_| format!("expected a thread count for {}, got {}", arg, value))?;
+                }
+                "--progressive" => config.progressive = switch(&arg, value()?)?,
+                "--passes" => {
+                    let value = value()?;
+                    config.passes = value.parse().map_err(|_| format!("expected a pass count for {}, got {}", arg, value))?;
+                }
+                "--snapshot-passes" => {
+                    let value = value()?;
+                    config.snapshot_passes = value.parse().map_err(|_| format!("expected a pass count for {}, got {}", arg, value))?;
+                }
+                "--snapshot-seconds" => {
+                    config.snapshot_seconds = number(&arg, value()?)?;
+                    if config.snapshot_seconds < 0.0 {
+                        return Err(format!("{} can't be negative", arg));
+                    }
+                }
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        if config.volume.is_some() && !matches!(config.scene, SceneKind::Volumes | SceneKind::Showcase) {
+            return Err("--volume needs the volumes or showcase scene".to_string());
+        }
+        //light splatted from light paths lands on pixels other than the one sampled, so it isn't in the
+        //noise estimate adaptive sampling stops pixels by
+        if config.adaptive_max_samples > 0 && matches!(config.integrator, IntegratorKind::Bidirectional | IntegratorKind::Metropolis) {
+            return Err("--adaptive can't be used with the bdpt and mlt integrators, their splats aren't counted by it".to_string());
+        }
+        if config.passes > 0 && !config.progressive {
+            return Err("--passes needs --progressive on".to_string());
+        }
+        //a render that runs until interrupted has nothing to show for itself without snapshots
+        if config.progressive && config.passes == 0 && config.snapshot_passes == 0 && config.snapshot_seconds == 0.0 {
+            return Err("--progressive on without --passes needs --snapshot-passes or --snapshot-seconds".to_string());
+        }
+        Ok(config)
+    }
+}
+
+fn number(option: &str, value: String) -> Result<f64, String> {
+    value.parse().map_err(|_| format!("expected a number for {}, got {}", option, value))
+}
+
+fn switch(option: &str, value: String) -> Result<bool, String> {
+    match value.as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("unknown value {} for {}, expected on or off", value, option)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn config_defaults() {
+        assert_eq!(parse(&[]).unwrap().light_selection, LightSelection::Bvh);
+    }
+
+    #[test]
+    fn config_scene() {
+        assert_eq!(parse(&[]).unwrap().scene, SceneKind::Default);
+        assert_eq!(parse(&["--scene", "glass"]).unwrap().scene, SceneKind::Glass);
+        assert_eq!(parse(&["--scene", "showcase"]).unwrap().scene, SceneKind::Showcase);
+        assert!(parse(&["--scene", "cornell"]).is_err());
+    }
+
+    #[test]
+    fn config_spectral() {
+        assert!(!parse(&[]).unwrap().spectral);
+        assert!(parse(&["--spectral", "on"]).unwrap().spectral);
+        assert!(parse(&["--spectral", "1"]).is_err());
+    }
+
+    #[test]
+    fn config_light_sampling() {
+        assert_eq!(parse(&["--light-sampling", "power"]).unwrap().light_selection, LightSelection::Power);
+        assert!(parse(&["--light-sampling"]).is_err());
+        assert!(parse(&["--light-sampling", "random"]).is_err());
+        assert!(parse(&["--lights"]).is_err());
+    }
+
+    #[test]
+    fn config_integrator() {
+        assert_eq!(parse(&[]).unwrap().integrator, IntegratorKind::Path);
+        assert_eq!(parse(&["--integrator", "ao"]).unwrap().integrator, IntegratorKind::AmbientOcclusion);
+        assert_eq!(parse(&["--integrator", "bdpt"]).unwrap().integrator, IntegratorKind::Bidirectional);
+        assert_eq!(parse(&["--integrator", "photon"]).unwrap().integrator, IntegratorKind::PhotonMapping);
+        assert_eq!(parse(&["--integrator", "mlt"]).unwrap().integrator, IntegratorKind::Metropolis);
+        assert!(parse(&["--integrator", "whitted"]).is_err());
+    }
+
+    #[test]
+    fn config_sky() {
+        let config = parse(&["--sky", "gradient", "--sun-elevation", "12.5", "--turbidity", "6"]).unwrap();
+        assert_eq!(config.sky_model, SkyModel::Gradient);
+        assert_eq!(config.sun_elevation, 12.5);
+        assert_eq!(config.turbidity, 6.0);
+        assert!(parse(&["--sun-azimuth", "west"]).is_err());
+        assert!(parse(&["--sun-elevation", "-10"]).is_err());
+        assert!(parse(&["--sun-elevation", "0"]).is_err());
+        assert!(parse(&["--sun-elevation", "95"]).is_err());
+        assert!(parse(&["--turbidity", "1"]).is_err());
+        assert!(parse(&["--turbidity", "12"]).is_err());
+    }
+
+    #[test]
+    fn config_aovs() {
+        assert!(!parse(&[]).unwrap().aovs);
+        assert!(parse(&["--aovs", "on"]).unwrap().aovs);
+        assert!(parse(&["--aovs", "yes"]).is_err());
+    }
+
+    #[test]
+    fn config_denoise() {
+        let config = parse(&["--denoise", "1.5", "--raw", "on"]).unwrap();
+        assert_eq!(config.denoise, 1.5);
+        assert!(config.raw);
+        assert_eq!(parse(&[]).unwrap().denoise, 0.0);
+        assert!(parse(&["--denoise", "-1"]).is_err());
+    }
+
+    #[test]
+    fn config_adaptive() {
+        let config = parse(&["--adaptive", "64", "--noise-threshold", "0.02", "--heatmap", "on"]).unwrap();
+        assert_eq!(config.adaptive_max_samples, 64);
+        assert_eq!(config.noise_threshold, 0.02);
+        assert!(config.heatmap);
+        assert!(parse(&["--adaptive", "2.5"]).is_err());
+        //in either order
+        assert!(parse(&["--adaptive", "64", "--integrator", "bdpt"]).is_err());
+        assert!(parse(&["--integrator", "mlt", "--adaptive", "64"]).is_err());
+        assert!(parse(&["--integrator", "photon", "--adaptive", "64"]).is_ok());
+        assert!(parse(&["--noise-threshold", "-0.01"]).is_err());
+    }
+
+    #[test]
+    fn config_sampler() {
+        assert_eq!(parse(&[]).unwrap().sampler, SamplerKind::Independent);
+        assert_eq!(parse(&["--sampler", "sobol"]).unwrap().sampler, SamplerKind::Sobol);
+        assert_eq!(parse(&["--sampler", "bluenoise"]).unwrap().sampler, SamplerKind::BlueNoise);
+        assert!(parse(&["--sampler", "random"]).is_err());
+    }
+
+    #[test]
+    fn config_seed() {
+        assert_eq!(parse(&[]).unwrap().seed, 0);
+        assert_eq!(parse(&["--seed", "1234"]).unwrap().seed, 1234);
+        assert!(parse(&["--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn config_filter() {
+        assert_eq!(parse(&[]).unwrap().filter, FilterKind::Box);
+        let config = parse(&["--filter", "mitchell", "--filter-radius", "1.5"]).unwrap();
+        assert_eq!(config.filter, FilterKind::Mitchell);
+        assert_eq!(config.filter_radius, 1.5);
+        assert!(parse(&["--filter", "sinc"]).is_err());
+        assert!(parse(&["--filter-radius", "-1"]).is_err());
+    }
+
+    #[test]
+    fn config_threads() {
+        assert_eq!(parse(&[]).unwrap().threads, 0);
+        assert_eq!(parse(&["--threads", "12"]).unwrap().threads, 12);
+        assert!(parse(&["--threads", "many"]).is_err());
+    }
+
+    #[test]
+    fn config_volume() {
+        assert!(parse(&[]).unwrap().volume.is_none());
+        let path = std::env::temp_dir().join("config_volume_test.raw");
+        std::fs::write(&path, [0u8; 4]).unwrap();
+        let file = path.to_str().unwrap();
+        assert_eq!(parse(&["--scene", "volumes", "--volume", file]).unwrap().volume, Some(path.clone()));
+        assert!(parse(&["--volume", file, "--scene", "showcase"]).is_ok());
+        assert!(parse(&["--volume", file]).is_err());
+        assert!(parse(&["--scene", "volumes", "--volume", "missing.vol"]).is_err());
+        assert!(parse(&["--scene", "volumes", "--volume", "Cargo.toml"]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn config_progressive() {
+        assert!(!parse(&[]).unwrap().progressive);
+        let config = parse(&["--progressive", "on", "--snapshot-passes", "4", "--snapshot-seconds", "0"]).unwrap();
+        assert!(config.progressive);
+        assert_eq!(config.snapshot_passes, 4);
+        assert_eq!(config.snapshot_seconds, 0.0);
+        assert!(parse(&["--snapshot-seconds", "-5"]).is_err());
+        assert_eq!(parse(&[]).unwrap().passes, 0);
+        assert_eq!(parse(&["--progressive", "on", "--passes", "64"]).unwrap().passes, 64);
+        assert!(parse(&["--passes", "64"]).is_err());
+        assert!(parse(&["--progressive", "on", "--passes", "all"]).is_err());
+        //without a pass count the snapshots are the only output
+        assert!(parse(&["--progressive", "on", "--snapshot-seconds", "0"]).is_err());
+        assert!(parse(&["--progressive", "on", "--passes", "8", "--snapshot-seconds", "0"]).is_ok());
+    }
+}
@@ -0,0 +1,93 @@
+use raytracer::{random_f64, INFINITY};
+use crate::material::Material;
+use crate::medium::Phase;
+use crate::vec3::{Vec3, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+
+//volume of constant density filling a closed boundary, like smoke or a cloud
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    density: f64,
+    phase_function: Material,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable + Send + Sync>, density: f64, albedo: Color, phase: Phase) -> ConstantMedium {
+        ConstantMedium { boundary, density, phase_function: Material::Volume { albedo, phase } }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+
+        //find where the ray enters and leaves the boundary, the ray may start inside
+        let rec_in = self.boundary.hit(r, -INFINITY, INFINITY)?;
+        let rec_out = self.boundary.hit(r, rec_in.t + 0.0001, INFINITY)?;
+
+        let t_in = rec_in.t.max(t_min).max(0.0);
+        let t_out = rec_out.t.min(t_max);
+        if t_in >= t_out {
+            return None;
+        }
+
+        let length = r.direction.length();
+        let distance_inside = (t_out - t_in) * length;
+        let hit_distance = -(1.0 - random_f64()).ln() / self.density;
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let mut ret_rec = HitRecord::default();
+        ret_rec.t = t_in + hit_distance / length;
+        ret_rec.point = r.at(ret_rec.t);
+        ret_rec.material = &self.phase_function;
+
+        //a volume has no surface, normal and face are arbitrary
+        ret_rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        ret_rec.front_face = true;
+
+        Some(ret_rec)
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.phase_function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn smoke(density: f64) -> ConstantMedium {
+        let boundary = Sphere::new(Vec3::origin(), 1.0, Material::Lambertian { albedo: Color::WHITE });
+        ConstantMedium::new(Box::new(boundary), density, Color::WHITE, Phase::Isotropic)
+    }
+
+    #[test]
+    fn constant_medium_transmittance() {
+        //a ray through the center crosses a distance of 2
+        let medium = smoke(0.5);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0));
+        let n = 100000;
+        let hits = (0..n).filter(|_| medium.hit(&r, 0.001, INFINITY).is_some()).count();
+        let expected = 1.0 - (-0.5f64 * 2.0).exp();
+        assert!((hits as f64 / n as f64 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn constant_medium_from_inside() {
+        let medium = smoke(100.0);
+        let r = Ray::new(Vec3::origin(), Vec3::new(0.0, 1.0, 0.0));
+        let rec = medium.hit(&r, 0.001, INFINITY).expect("Should not be none!");
+        assert!(rec.t > 0.0 && rec.t < 1.0);
+    }
+
+    #[test]
+    fn constant_medium_miss() {
+        let medium = smoke(100.0);
+        let r = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(medium.hit(&r, 0.001, INFINITY).is_none());
+    }
+}
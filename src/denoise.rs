@@ -0,0 +1,156 @@
+use crate::aov::{Aovs, AovPass};
+use crate::vec3::{Color, dot};
+
+//passes of the a-trous filter, each one doubles the spacing of the kernel
+pub const DENOISE_ITERATIONS: u32 = 5;
+//how far guides may differ before neighbours stop counting, larger is blurrier
+const SIGMA_COLOR: f64 = 0.4;
+const SIGMA_NORMAL: f64 = 0.1;
+const SIGMA_ALBEDO: f64 = 0.1;
+//relative to the depth of the center pixel and the spacing of the kernel
+const SIGMA_DEPTH: f64 = 0.02;
+
+//B3 spline the kernel is made of
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+//guides of a pixel, the first hits of its camera rays averaged
+#[derive(Clone, Copy)]
+struct Guide {
+    albedo: Color,
+    normal: Color,
+    depth: f64,
+}
+
+//edge-avoiding a-trous wavelet filter. image holds pixels summed over samples_per_pixel, counted from
+//the lower left corner like the aovs, and so does the result. neighbours are averaged in as long as
+//their albedo, normal, depth and color are close to those of the pixel, strength scales how close the
+//colors have to be
+pub fn denoise(image: &[Color], aovs: &Aovs, width: u32, height: u32, samples_per_pixel: u32, strength: f64) -> Vec<Color> {
+    let scale = 1.0 / samples_per_pixel as f64;
+    let mut guides = Vec::with_capacity(image.len());
+    for j in 0..height {
+        for i in 0..width {
+            guides.push(Guide {
+                albedo: aovs.value(AovPass::Albedo, i, j),
+                normal: aovs.value(AovPass::Normal, i, j),
+                depth: aovs.value(AovPass::Depth, i, j).x(),
+            });
+        }
+    }
+
+    let mut current: Vec<Color> = image.iter().map(|&color| scale * color).collect();
+    let mut sigma_color = SIGMA_COLOR * strength;
+    for iteration in 0..DENOISE_ITERATIONS {
+        if sigma_color <= 0.0 {
+            break;
+        }
+        let step = 1 << iteration;
+        current = filter(&current, &guides, width, height, step, sigma_color);
+        //later passes reach further, so they only average colors that are already close
+        sigma_color /= 2.0;
+    }
+
+    current.iter().map(|&color| color / scale).collect()
+}
+
+fn filter(image: &[Color], guides: &[Guide], width: u32, height: u32, step: i64, sigma_color: f64) -> Vec<Color> {
+    let mut filtered = Vec::with_capacity(image.len());
+    for j in 0..height as i64 {
+        for i in 0..width as i64 {
+            let k = (j * width as i64 + i) as usize;
+            let center = guides[k];
+            let center_color = tone_map(image[k]);
+
+            let mut sum = Color::BLACK;
+            let mut weight_sum = 0.0;
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let x = i + (dx as i64 - 2) * step;
+                    let y = j + (dy as i64 - 2) * step;
+                    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                        continue;
+                    }
+                    let q = (y * width as i64 + x) as usize;
+                    let guide = guides[q];
+
+                    let color_distance = (tone_map(image[q]) - center_color).length_squared();
+                    let albedo_distance = (guide.albedo - center.albedo).length_squared();
+                    let normal_distance = (1.0 - dot(guide.normal, center.normal)).max(0.0);
+                    let depth_distance = (guide.depth - center.depth).abs() / (SIGMA_DEPTH * step as f64 * center.depth.max(1e-3));
+
+                    let weight = kx * ky * (
+                        -color_distance / (sigma_color * sigma_color)
+                        - albedo_distance / (SIGMA_ALBEDO * SIGMA_ALBEDO)
+                        - normal_distance / SIGMA_NORMAL
+                        - depth_distance
+                    ).exp();
+                    sum += weight * image[q];
+                    weight_sum += weight;
+                }
+            }
+            //the center always has a weight, so the sum can't be 0
+            filtered.push(sum / weight_sum);
+        }
+    }
+    filtered
+}
+
+//squeezes bright colors so single fireflies don't stop their neighbours from being averaged. negative
+//components, which filters with negative lobes can leave, are clamped so they can't divide by 0
+fn tone_map(color: Color) -> Color {
+    let (r, g, b) = color.get_components();
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    Color::new(r / (1.0 + r), g / (1.0 + g), b / (1.0 + b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracer::random_f64;
+    use crate::aov::AovSample;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 16);
+        let mut aovs = Aovs::new(width, height);
+        let mut image = Vec::new();
+        for j in 0..height {
+            for i in 0..width {
+                //left half faces up and is dark, the right half faces the camera and is bright
+                let left = i < width / 2;
+                let normal = if left {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(0.0, 0.0, -1.0)};
+                let sample = AovSample {
+                    albedo: Color::WHITE,
+                    normal,
+                    depth: 5.0,
+                    position: Vec3::origin(),
+                    object_id: 1,
+                    material_id: 1,
+                    occlusion: 1.0,
+                };
+                aovs.add_sample(i, j, 0, &sample);
+                let value = if left {0.2} else {0.8};
+                image.push((value + 0.1 * (random_f64() - 0.5)) * Color::WHITE);
+            }
+        }
+
+        let denoised = denoise(&image, &aovs, width, height, 1, 1.0);
+        let error = |pixels: &[Color]| -> f64 {
+            pixels.iter().enumerate().map(|(k, color)| {
+                let expected = if (k as u32 % width) < width / 2 {0.2} else {0.8};
+                (color.x() - expected).abs()
+            }).sum::<f64>() / pixels.len() as f64
+        };
+        assert!(error(&denoised) < 0.5 * error(&image));
+
+        //with strength 0 nothing changes
+        assert_eq!(denoise(&image, &aovs, width, height, 1, 0.0), image);
+    }
+
+    #[test]
+    fn tone_map_clamps_negative_colors() {
+        let mapped = tone_map(Color::new(-1.0, -3.0, 1.0));
+        assert_eq!(mapped, Color::new(0.0, 0.0, 0.5));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::vec3::Color;
+
+//units of a splat per unit of color. splats are summed in fixed point, where the order they are added
+//in doesn't change the result, so the image is the same however the work was split between threads
+const FIXED_POINT_SCALE: f64 = (1u64 << 24) as f64;
+
+pub fn to_fixed(value: f64) -> i64 {
+    (value * FIXED_POINT_SCALE).round() as i64
+}
+
+pub fn from_fixed(sum: i64) -> f64 {
+    sum as f64 / FIXED_POINT_SCALE
+}
+
+//image light tracing adds to, with pixels summed over samples like those of the camera paths
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<[i64; 3]>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film { width, height, pixels: vec![[0; 3]; (width * height) as usize] }
+    }
+
+    //adds color to the pixel containing film coordinates u, v in [0, 1), as passed to Camera::get_ray
+    pub fn splat(&mut self, u: f64, v: f64, color: Color) {
+        let i = ((u * self.width as f64) as u32).min(self.width - 1);
+        let j = ((v * self.height as f64) as u32).min(self.height - 1);
+        let pixel = &mut self.pixels[(j * self.width + i) as usize];
+        let (r, g, b) = color.get_components();
+        for (sum, value) in pixel.iter_mut().zip([r, g, b]) {
+            *sum = sum.wrapping_add(to_fixed(value));
+        }
+    }
+
+    //pixel i, j counted from the lower left corner
+    pub fn pixel(&self, i: u32, j: u32) -> Color {
+        let [r, g, b] = self.pixels[(j * self.width + i) as usize];
+        Color::new(from_fixed(r), from_fixed(g), from_fixed(b))
+    }
+
+    pub fn add(&mut self, other: &Film) {
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            for (sum, value) in pixel.iter_mut().zip(other) {
+                *sum = sum.wrapping_add(*value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splat_and_add() {
+        let mut film = Film::new(4, 2);
+        film.splat(0.3, 0.6, Color::WHITE);
+        film.splat(0.99, 0.1, Color::WHITE);
+
+        let mut sum = Film::new(4, 2);
+        sum.add(&film);
+        sum.add(&film);
+        assert_eq!(sum.pixel(1, 1), 2.0 * Color::WHITE);
+        assert_eq!(sum.pixel(3, 0), 2.0 * Color::WHITE);
+        assert_eq!(sum.pixel(0, 0), Color::BLACK);
+    }
+
+    #[test]
+    fn splats_sum_in_any_order() {
+        let colors = [Color::new(0.1, 1e6, 0.3), Color::new(0.7, -1e6, 1e-3), Color::new(0.2, 0.5, 0.25)];
+        let mut forward = Film::new(1, 1);
+        for &color in colors.iter() {
+            forward.splat(0.5, 0.5, color);
+        }
+        let mut backward = Film::new(1, 1);
+        for &color in colors.iter().rev() {
+            let mut single = Film::new(1, 1);
+            single.splat(0.5, 0.5, color);
+            backward.add(&single);
+        }
+        assert_eq!(forward.pixel(0, 0), backward.pixel(0, 0));
+        assert!((forward.pixel(0, 0) - Color::new(1.0, 0.5, 0.551)).length() < 1e-6);
+    }
+}
@@ -0,0 +1,147 @@
+use raytracer::PI;
+
+//bins of the table each axis of a filter is sampled from
+const FILTER_TABLE_SIZE: usize = 256;
+
+//pixel reconstruction filter chosen in the configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    //half width in pixels the filter reaches out to when none is given
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 2.0,
+        }
+    }
+}
+
+//separable reconstruction filter, used by filter importance sampling: camera rays are offset from the
+//pixel center in proportion to the filter's magnitude, and carry its sign as their weight, so each
+//sample only adds to the pixel it was taken for. weights are 1 on average, so pixels stay the plain
+//mean of their weighted samples, which can't blow up when a few samples of opposite sign cancel out
+pub struct Filter {
+    kind: FilterKind,
+    radius: f64,
+    //running sums of the magnitude of the filter over the bins of [-radius, radius], from 0 to 1
+    cdf: Vec<f64>,
+    //magnitude and signed integral of the filter along one axis
+    magnitude: f64,
+    integral: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Filter {
+        let mut filter = Filter { kind, radius, cdf: Vec::with_capacity(FILTER_TABLE_SIZE + 1), magnitude: 0.0, integral: 0.0 };
+        let width = 2.0 * radius / FILTER_TABLE_SIZE as f64;
+        filter.cdf.push(0.0);
+        for k in 0..FILTER_TABLE_SIZE {
+            //averaged over the bin, so bins a zero crossing passes through aren't left out
+            for s in 0..8 {
+                let value = filter.evaluate(-radius + (k as f64 + (s as f64 + 0.5) / 8.0) * width) * width / 8.0;
+                filter.magnitude += value.abs();
+                filter.integral += value;
+            }
+            filter.cdf.push(filter.magnitude);
+        }
+        for value in filter.cdf.iter_mut() {
+            *value /= filter.magnitude;
+        }
+        filter
+    }
+
+    //value of the filter along one axis at offset x from the pixel center
+    pub fn evaluate(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => self.radius - x,
+            FilterKind::Gaussian => {
+                //falls to zero at the radius, with a standard deviation of a third of it
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(self.radius)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+
+    //offset from the pixel center for the uniform numbers u, and the weight of the sample taken there
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (x, pdf_x) = self.sample_axis(u.0);
+        let (y, pdf_y) = self.sample_axis(u.1);
+        let value = self.evaluate(x) * self.evaluate(y);
+        let pdf = pdf_x * pdf_y;
+        let weight = if pdf > 0.0 {value / (pdf * self.integral * self.integral)} else {0.0};
+        ((x, y), weight)
+    }
+
+    //inverts the tabulated distribution, which keeps stratified numbers stratified
+    fn sample_axis(&self, u: f64) -> (f64, f64) {
+        let k = (self.cdf.partition_point(|&c| c <= u) - 1).min(FILTER_TABLE_SIZE - 1);
+        let bin = self.cdf[k + 1] - self.cdf[k];
+        let t = if bin > 0.0 {(u - self.cdf[k]) / bin} else {0.5};
+        let width = 2.0 * self.radius / FILTER_TABLE_SIZE as f64;
+        let x = -self.radius + (k as f64 + t) * width;
+        (x, bin / width)
+    }
+}
+
+//Mitchell-Netravali cubic with B = C = 1/3, for x in [-2, 2]
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+    let x = x.abs();
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {1.0} else {(PI * x).sin() / (PI * x)}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_weigh_like_the_filter() {
+        for kind in [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos] {
+            let filter = Filter::new(kind, kind.default_radius());
+            let n = 256;
+            let (mut weight_sum, mut negative) = (0.0, false);
+            assert!(filter.magnitude >= filter.integral);
+            for a in 0..n {
+                for b in 0..n {
+                    let u = ((a as f64 + 0.5) / n as f64, (b as f64 + 0.5) / n as f64);
+                    let ((x, y), weight) = filter.sample(u);
+                    assert!(x.abs() <= filter.radius && y.abs() <= filter.radius);
+                    negative |= weight < 0.0;
+                    weight_sum += weight;
+                }
+            }
+            //the weights average to 1, so the image is as bright as with any other filter
+            assert!((weight_sum / (n * n) as f64 - 1.0).abs() < 0.02, "{:?}", kind);
+            assert_eq!(negative, matches!(kind, FilterKind::Mitchell | FilterKind::Lanczos), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use raytracer::random_f64;
+use crate::material::Material;
+use crate::medium::Phase;
+use crate::perlin::Perlin;
+use crate::vec3::{Vec3, Point3, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+
+//densities on a regular grid of voxels spanning the unit cube, x varies fastest
+pub struct DensityGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>) -> DensityGrid {
+        assert_eq!(data.len(), nx * ny * nz, "grid data does not match its resolution");
+        DensityGrid { nx, ny, nz, data }
+    }
+
+    //loads a Mitsuba .vol grid, or a .raw file holding a cube of little-endian f32 densities
+    pub fn load(path: &Path) -> io::Result<DensityGrid> {
+        let bytes = fs::read(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("vol") => DensityGrid::parse_vol(&bytes),
+            Some("raw") => DensityGrid::parse_raw(&bytes),
+            _ => Err(invalid_data("expected a .vol or .raw file")),
+        }
+    }
+
+    fn parse_vol(bytes: &[u8]) -> io::Result<DensityGrid> {
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(invalid_data("not a version 3 .vol file"));
+        }
+        let int = |i: usize| i32::from_le_bytes([bytes[i], bytes[i+1], bytes[i+2], bytes[i+3]]);
+        //only float32 encoding is supported
+        if int(4) != 1 {
+            return Err(invalid_data("unsupported .vol encoding"));
+        }
+        let (nx, ny, nz, channels) = (int(8), int(12), int(16), int(20));
+        if nx <= 0 || ny <= 0 || nz <= 0 || channels <= 0 {
+            return Err(invalid_data("invalid .vol resolution"));
+        }
+        let (nx, ny, nz, channels) = (nx as usize, ny as usize, nz as usize, channels as usize);
+
+        //the bounding box in the header is ignored, the medium places the grid
+        let values = floats(&bytes[48..]);
+        if values.len() != nx * ny * nz * channels {
+            return Err(invalid_data("truncated .vol file"));
+        }
+        let data = values.into_iter().step_by(channels).collect();
+        Ok(DensityGrid::new(nx, ny, nz, data))
+    }
+
+    fn parse_raw(bytes: &[u8]) -> io::Result<DensityGrid> {
+        let values = floats(bytes);
+        let n = (values.len() as f64).cbrt().round() as usize;
+        if n == 0 || n * n * n != values.len() || !bytes.len().is_multiple_of(4) {
+            return Err(invalid_data(".raw file does not hold a cube of f32 values"));
+        }
+        Ok(DensityGrid::new(n, n, n, values))
+    }
+
+    //billowy cloud made of noise, fading out towards the sides of the grid
+    pub fn cloud(resolution: usize, seed: u64) -> DensityGrid {
+        let perlin = Perlin::new(seed);
+        let n = resolution;
+        let mut data = Vec::with_capacity(n * n * n);
+        for k in 0..n {
+            for j in 0..n {
+                for i in 0..n {
+                    let p = Point3::new(i as f64 + 0.5, j as f64 + 0.5, k as f64 + 0.5) / n as f64;
+                    let falloff = 1.0 - 2.0 * (p - Point3::new(0.5, 0.5, 0.5)).length();
+                    let density = falloff + 0.6 * perlin.fbm(4.0 * p, 5) - 0.1;
+                    data.push(density.max(0.0) as f32);
+                }
+            }
+        }
+        DensityGrid::new(n, n, n, data)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.data.iter().fold(0.0f32, |a, &b| a.max(b)) as f64
+    }
+
+    fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i] as f64
+    }
+
+    //trilinear interpolation between voxel centers, p in the unit cube
+    pub fn density(&self, p: Point3) -> f64 {
+        let (x, y, z) = p.get_components();
+        let (i0, i1, u) = cell(x, self.nx);
+        let (j0, j1, v) = cell(y, self.ny);
+        let (k0, k1, w) = cell(z, self.nz);
+
+        let lerp = |a: f64, b: f64, t: f64| a + t * (b - a);
+        lerp(
+            lerp(lerp(self.voxel(i0, j0, k0), self.voxel(i1, j0, k0), u), lerp(self.voxel(i0, j1, k0), self.voxel(i1, j1, k0), u), v),
+            lerp(lerp(self.voxel(i0, j0, k1), self.voxel(i1, j0, k1), u), lerp(self.voxel(i0, j1, k1), self.voxel(i1, j1, k1), u), v),
+            w,
+        )
+    }
+}
+
+//neighbouring voxels along one axis and the interpolation weight between them
+fn cell(x: f64, n: usize) -> (usize, usize, f64) {
+    let x = (x * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+    let i = (x.floor() as usize).min(n - 1);
+    (i, (i + 1).min(n - 1), x - i as f64)
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+//volume with density varying along a grid, filling an axis aligned box
+pub struct HeterogeneousMedium {
+    min: Point3,
+    max: Point3,
+    grid: DensityGrid,
+    density_scale: f64,
+    //upper bound of the density, free flights are sampled against it
+    majorant: f64,
+    phase_function: Material,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(min: Point3, max: Point3, grid: DensityGrid, density_scale: f64, albedo: Color, phase: Phase) -> HeterogeneousMedium {
+        let majorant = grid.max() * density_scale;
+        HeterogeneousMedium { min, max, grid, density_scale, majorant, phase_function: Material::Volume { albedo, phase } }
+    }
+
+    fn density(&self, p: Point3) -> f64 {
+        self.density_scale * self.grid.density((p - self.min) / (self.max - self.min))
+    }
+
+    //parameter range of the ray inside the box
+    fn clip(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let origin = r.origin.get_components();
+        let direction = r.direction.get_components();
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for ((o, d), (lo, hi)) in [origin.0, origin.1, origin.2].into_iter()
+                .zip([direction.0, direction.1, direction.2])
+                .zip([(self.min.x(), self.max.x()), (self.min.y(), self.max.y()), (self.min.z(), self.max.z())]) {
+            //parallel to the slab, 1 / d would give NaN for origins on its planes
+            if d == 0.0 {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let inv_d = 1.0 / d;
+            let (near, far) = if inv_d < 0.0 {((hi - o) * inv_d, (lo - o) * inv_d)} else {((lo - o) * inv_d, (hi - o) * inv_d)};
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (t_in, t_out) = self.clip(r, t_min, t_max)?;
+
+        //delta tracking: flights are sampled against the majorant, collisions are real
+        //with probability density / majorant and null otherwise
+        let length = r.direction.length();
+        let mut t = t_in;
+        loop {
+            t -= (1.0 - random_f64()).ln() / (self.majorant * length);
+            if t >= t_out {
+                return None;
+            }
+            if random_f64() * self.majorant < self.density(r.at(t)) {
+                break;
+            }
+        }
+
+        let mut ret_rec = HitRecord::default();
+        ret_rec.t = t;
+        ret_rec.point = r.at(t);
+        ret_rec.material = &self.phase_function;
+
+        //a volume has no surface, normal and face are arbitrary
+        ret_rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        ret_rec.front_face = true;
+
+        Some(ret_rec)
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.phase_function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracer::INFINITY;
+
+    fn ramp() -> DensityGrid {
+        //density rises along x from 0 to 1
+        let data = (0..8).map(|i| (i % 2) as f32).collect();
+        DensityGrid::new(2, 2, 2, data)
+    }
+
+    #[test]
+    fn grid_interpolation() {
+        let grid = ramp();
+        assert_eq!(grid.density(Point3::new(0.25, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(Point3::new(0.5, 0.1, 0.9)), 0.5);
+        assert_eq!(grid.density(Point3::new(1.0, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.max(), 1.0);
+    }
+
+    #[test]
+    fn delta_tracking_transmittance() {
+        //along the ramp the optical depth over the box is 0.5 * scale
+        let medium = HeterogeneousMedium::new(Point3::origin(), Point3::new(1.0, 1.0, 1.0), ramp(), 2.0, Color::WHITE, Phase::Isotropic);
+        let r = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        let n = 100000;
+        let passed = (0..n).filter(|_| medium.hit(&r, 0.001, INFINITY).is_none()).count();
+        let expected = (-0.5f64 * 2.0).exp();
+        assert!((passed as f64 / n as f64 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn clip_parallel_to_slab() {
+        let medium = HeterogeneousMedium::new(Point3::origin(), Point3::new(1.0, 1.0, 1.0), ramp(), 2.0, Color::WHITE, Phase::Isotropic);
+        //runs along the bottom face, inside the y slab
+        let r = Ray::new(Point3::new(-1.0, 0.0, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(medium.clip(&r, 0.0, INFINITY), Some((1.0, 2.0)));
+        //runs parallel to the box, above it
+        let r = Ray::new(Point3::new(-1.0, 1.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(medium.clip(&r, 0.0, INFINITY), None);
+    }
+
+    #[test]
+    fn load_vol_and_raw() {
+        let values: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let mut vol = b"VOL".to_vec();
+        vol.push(3);
+        for header in [1i32, 2, 2, 2, 1] {
+            vol.extend(header.to_le_bytes());
+        }
+        vol.extend([0.0f32; 6].iter().flat_map(|v| v.to_le_bytes()));
+        vol.extend(&data);
+
+        //named after the process and test, so parallel test runs don't overwrite each other's files
+        let dir = std::env::temp_dir();
+        let name = format!("raytracer_{}_load_vol_and_raw", std::process::id());
+        let vol_path = dir.join(format!("{}.vol", name));
+        let raw_path = dir.join(format!("{}.raw", name));
+        fs::write(&vol_path, vol).unwrap();
+        fs::write(&raw_path, &data).unwrap();
+
+        for path in [vol_path, raw_path] {
+            let grid = DensityGrid::load(&path).expect("grid should load");
+            assert_eq!((grid.nx, grid.ny, grid.nz), (2, 2, 2));
+            assert_eq!(grid.voxel(1, 0, 1), 5.0);
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -2,29 +2,68 @@ use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::{Vec3, Point3, dot, Color};
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    //solid angle density of random() choosing direction from origin, for objects used as lights
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    //direction from origin towards a point of the object, chosen by two uniform numbers
+    fn random(&self, _origin: Point3, _u: (f64, f64)) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    //estimate of the light the object emits, used to choose between lights
+    fn power(&self) -> f64 {
+        0.0
+    }
+
+    //point on the object chosen by u with the normal of the side chosen by side, for objects used as
+    //lights. both sides are chosen equally often, as emitted() doesn't depend on the side either
+    fn sample_surface(&self, _u: (f64, f64), _side: f64) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    //area density of sample_surface choosing a point together with its side
+    fn surface_pdf(&self) -> f64 {
+        0.0
+    }
+
+    //corners of a box enclosing the object, None if it is unbounded
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        None
+    }
+
+    //material all hits on the object carry, None if there isn't a single one
+    fn material(&self) -> Option<&Material> {
+        None
+    }
 }
 
+//material hit records point to when nothing else is set
+static DEFAULT_MATERIAL: Material = Material::Lambertian { albedo: Color::YELLOW };
+
 #[derive(Debug, Clone, Copy)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub point: Point3,
     pub normal: Vec3,
     pub t: f64,
 
     pub front_face: bool,
 
-    pub material: Material,
+    pub material: &'a Material,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     //init
-    pub fn default() -> HitRecord {
+    pub fn default() -> HitRecord<'a> {
         HitRecord {
             point: Vec3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
             t: 0.0,
             front_face: false,
-            material: Material::Lambertian { albedo: Color::YELLOW },
+            material: &DEFAULT_MATERIAL,
         }
     }
     
@@ -58,7 +97,14 @@ impl HitRecord {
         self.material = val
     }
      */
-    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) -> () {
+    //record on the side of a surface with the given outward normal chosen by a uniform number
+    pub fn on_side(point: Point3, outward_normal: Vec3, material: &'a Material, side: f64) -> HitRecord<'a> {
+        let front_face = side < 0.5;
+        let normal = if front_face {outward_normal} else {-outward_normal};
+        HitRecord { point, normal, t: 0.0, front_face, material }
+    }
+
+    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
         self.front_face = dot(ray.direction, outward_normal) < 0.0;
         self.normal = if self.front_face {outward_normal} else {- outward_normal};
     }
@@ -3,31 +3,52 @@ use crate::ray::Ray;
 
 pub struct HittableList {
     pub list: Vec<Box<dyn Hittable + Send + Sync>>,
+    //id of the material of each object, see material_id
+    material_ids: Vec<u32>,
 }
 
 impl HittableList {
     pub fn new(list: Vec<Box<dyn Hittable + Send + Sync>>) -> HittableList {
-        HittableList { list }
+        //materials are numbered from 1 in the order they first appear, objects whose materials have
+        //the same parameters share the number
+        let mut materials: Vec<String> = Vec::new();
+        let material_ids = list.iter().map(|object| match object.material() {
+            Some(material) => {
+                let material = format!("{:?}", material);
+                let index = materials.iter().position(|m| *m == material).unwrap_or_else(|| {
+                    materials.push(material);
+                    materials.len() - 1
+                });
+                index as u32 + 1
+            }
+            None => 0,
+        }).collect();
+        HittableList { list, material_ids }
     }
-}
 
-impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    //id of the material of the object at index, 0 for objects without a single material
+    pub fn material_id(&self, index: usize) -> u32 {
+        self.material_ids[index]
+    }
+
+    //closest hit together with the index of the object in list that was hit
+    pub fn hit_object(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(usize, HitRecord<'_>)> {
         let mut ret_rec = None;
         let mut closest_so_far = t_max;
         
-        for object in &self.list {
-            let op_rec = object.hit(r, t_min, closest_so_far);
-            match op_rec {
-                Some(rec) => {
-                    closest_so_far = rec.t;
-                    ret_rec = Some(rec);
-                },
-
-                None => (),
+        for (i, object) in self.list.iter().enumerate() {
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                ret_rec = Some((i, rec));
             }
         }
 
-        return ret_rec;
+        ret_rec
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hit_object(r, t_min, t_max).map(|(_, rec)| rec)
     }
 }
\ No newline at end of file
@@ -0,0 +1,556 @@
+use raytracer::{INFINITY, PI};
+use crate::ray::Ray;
+use crate::hittable::{Hittable, HitRecord};
+use crate::material::{Material, scatter, emitted, evaluate, is_specular_sample};
+use crate::vec3::{Vec3, Color, dot};
+use crate::spectrum::{SampledWavelengths, Spectrum};
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::onb::Onb;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::bdpt::BdptIntegrator;
+use crate::photon_map::{PhotonMap, trace_caustic_photons};
+use crate::mlt::MltIntegrator;
+use crate::{PHOTONS_PER_PASS, PHOTON_RADIUS, MLT_BOOTSTRAP_SAMPLES};
+
+//bounces before Russian roulette starts ending paths
+pub const ROULETTE_DEPTH: u32 = 3;
+//scattering events inside a medium before a random walk counts as absorbed
+pub const MAX_WALK_STEPS: u32 = 256;
+//distance at which rays leaving the scene reach the sky, bounds the fog in front of it
+pub const SKY_DISTANCE: f64 = 100.0;
+//fraction of the photons the search area keeps between passes of progressive photon mapping
+pub const PHOTON_ALPHA: f64 = 2.0 / 3.0;
+
+//algorithm computing the light arriving at the camera
+pub trait Integrator {
+    //rgb radiance arriving along camera ray r, taken as the index-th sample of pixel.
+    //light reaching other pixels can be splatted onto film
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, pixel: (u32, u32), index: u32, film: &mut Film) -> Color;
+
+    //whether li splats onto the film, the others are handed one without pixels
+    fn splats(&self) -> bool {
+        false
+    }
+}
+
+//integrator chosen in the configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IntegratorKind {
+    Path,
+    Direct,
+    Bidirectional,
+    PhotonMapping,
+    Metropolis,
+    AmbientOcclusion,
+    Debug,
+}
+
+pub fn create(
+        kind: IntegratorKind,
+        max_depth: u32,
+        ao_distance: f64,
+        large_step_probability: f64,
+        max_samples: u32,
+        camera: Camera,
+        scene: &Scene
+    ) -> Box<dyn Integrator + Send + Sync> {
+
+    match kind {
+        IntegratorKind::Path => Box::new(PathIntegrator { max_depth }),
+        IntegratorKind::PhotonMapping => Box::new(PhotonMappingIntegrator::new(scene, max_depth, max_samples, PHOTONS_PER_PASS, PHOTON_RADIUS)),
+        IntegratorKind::Metropolis => Box::new(MltIntegrator::new(scene, camera, max_depth, large_step_probability, MLT_BOOTSTRAP_SAMPLES)),
+        IntegratorKind::Direct => Box::new(DirectLightingIntegrator { max_depth }),
+        IntegratorKind::Bidirectional => Box::new(BdptIntegrator { max_depth, camera }),
+        IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusionIntegrator { distance: ao_distance }),
+        IntegratorKind::Debug => Box::new(DebugIntegrator),
+    }
+}
+
+//unidirectional path tracer with next event estimation
+pub struct PathIntegrator {
+    pub max_depth: u32,
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, _pixel: (u32, u32), _index: u32, _film: &mut Film) -> Color {
+        let lambda = SampledWavelengths::new(r.wavelength, scene.spectral);
+        lambda.to_rgb(trace_path(r, scene, &lambda, sampler, self.max_depth, u32::MAX, None))
+    }
+}
+
+//path tracer that takes caustics from photon maps instead of paths hitting emitters after specular
+//bounces. each pass has its own photons and a smaller radius than the one before, as in progressive
+//photon mapping. the k-th sample of a pixel gathers from pass k, so the radius shrinks as samples
+//are added and the average over them converges
+pub struct PhotonMappingIntegrator {
+    pub max_depth: u32,
+    passes: Vec<(PhotonMap, f64)>,
+}
+
+impl PhotonMappingIntegrator {
+    pub fn new(scene: &Scene, max_depth: u32, pass_count: u32, photons_per_pass: usize, radius: f64) -> PhotonMappingIntegrator {
+        let mut passes = Vec::new();
+        let mut radius_squared = radius * radius;
+        for i in 0..pass_count {
+            passes.push((trace_caustic_photons(scene, photons_per_pass, max_depth), radius_squared.sqrt()));
+            radius_squared *= (i as f64 + 1.0 + PHOTON_ALPHA) / (i as f64 + 2.0);
+        }
+        PhotonMappingIntegrator { max_depth, passes }
+    }
+}
+
+impl Integrator for PhotonMappingIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, _pixel: (u32, u32), index: u32, _film: &mut Film) -> Color {
+        let lambda = SampledWavelengths::new(r.wavelength, scene.spectral);
+        //there is a pass for every sample up to the maximum, any past it share the last one
+        let (ref map, radius) = self.passes[(index as usize).min(self.passes.len() - 1)];
+        lambda.to_rgb(trace_path(r, scene, &lambda, sampler, self.max_depth, u32::MAX, Some((map, radius))))
+    }
+}
+
+//light reaching the first non-specular surface directly from emitters and the sky,
+//specular surfaces on the way there are followed up to max_depth
+pub struct DirectLightingIntegrator {
+    pub max_depth: u32,
+}
+
+impl Integrator for DirectLightingIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, _pixel: (u32, u32), _index: u32, _film: &mut Film) -> Color {
+        let lambda = SampledWavelengths::new(r.wavelength, scene.spectral);
+        lambda.to_rgb(trace_path(r, scene, &lambda, sampler, self.max_depth, 1, None))
+    }
+}
+
+//fraction of the hemisphere above the first surface that isn't blocked within distance
+pub struct AmbientOcclusionIntegrator {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, sampler: &mut dyn Sampler, _pixel: (u32, u32), _index: u32, _film: &mut Film) -> Color {
+        let rec = match scene.world.hit(r, 0.001, INFINITY) {
+            Some(rec) => rec,
+            None => return Color::WHITE,
+        };
+
+        //the cosine of the estimate cancels with the pdf of the direction
+        let direction = cosine_direction(rec.normal, sampler.get_2d());
+        let occlusion_ray = Ray::new(rec.point, direction);
+        if scene.world.hit(&occlusion_ray, 0.001, self.distance).is_some() {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        }
+    }
+}
+
+//shows the normal of the first surface, facing the camera, mapped to colors
+pub struct DebugIntegrator;
+
+impl Integrator for DebugIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _sampler: &mut dyn Sampler, _pixel: (u32, u32), _index: u32, _film: &mut Film) -> Color {
+        match scene.world.hit(r, 0.001, INFINITY) {
+            Some(rec) => 0.5 * (rec.normal + Color::WHITE),
+            None => Color::BLACK,
+        }
+    }
+}
+
+//follows the path of ray r through the scene and returns the light arriving along it.
+//the path is extended iteratively, Russian roulette ends it after the first few bounces.
+//after max_diffuse non-specular bounces only light reached by the last one is collected.
+//with a photon map and its radius, caustics are estimated from the photons at non-specular hits
+//and emitters reached over specular bounces from one are left out
+fn trace_path(
+        r: &Ray,
+        scene: &Scene,
+        lambda: &SampledWavelengths,
+        sampler: &mut dyn Sampler,
+        max_depth: u32,
+        max_diffuse: u32,
+        photons: Option<(&PhotonMap, f64)>
+    ) -> Color {
+
+    let mut radiance = Color::BLACK;
+    let mut throughput = Color::WHITE;
+    let mut r = *r;
+    //density the last bounce chose r with, None if it was specular or there was none
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut diffuse_bounces = 0;
+    //specular bounces since the last non-specular one, None before it or after scattering in a medium
+    let mut specular_chain: Option<u32> = None;
+
+    for depth in 0..max_depth {
+
+        //random walk through the medium the ray travels in, until it reaches a surface
+        let mut steps = 0;
+        let op_rec = loop {
+            let op_rec = scene.world.hit(&r, 0.001, INFINITY);
+            let medium = match r.medium.or(scene.fog) {
+                Some(medium) => medium.sampled(lambda),
+                None => break op_rec,
+            };
+
+            let length = r.direction.length();
+            let distance_max = op_rec.as_ref().map_or(SKY_DISTANCE, |rec| rec.t * length);
+            let (distance, weight) = medium.sample_distance(distance_max, sampler.get_2d());
+            throughput = throughput * weight;
+
+            match distance {
+                Some(distance) => {
+                    steps += 1;
+                    if steps > MAX_WALK_STEPS || diffuse_bounces >= max_diffuse {return radiance;}
+                    //lights are sampled at the scattering event like at a surface, with the phase function
+                    //in place of the bsdf
+                    let phase = Material::Volume { albedo: Color::WHITE, phase: medium.phase };
+                    let event = HitRecord { point: r.at(distance / length), normal: Vec3::origin(), t: distance / length, front_face: true, material: &phase };
+                    radiance += throughput * sample_lights(&r, &event, scene, lambda, sampler);
+                    diffuse_bounces += 1;
+
+                    let direction = medium.sample_phase(r.direction, sampler.get_2d());
+                    bsdf_pdf = Some(medium.phase.pdf(r.direction, direction));
+                    specular_chain = None;
+                    r = r.bounce(event.point, direction);
+                }
+                None => break op_rec,
+            }
+        };
+
+        let rec = match op_rec {
+            Some(rec) => rec,
+            None => {
+                //no hit for ray, add the sky's light and end the path
+                radiance += throughput * sky_radiance(&r, scene, lambda, bsdf_pdf);
+                break;
+            }
+        };
+
+        //lights that were also sampled directly at the previous bounce are weighted against it
+        let mut emitted = emitted(rec.material, lambda);
+        if let (Some(pdf), false) = (bsdf_pdf, emitted == Color::BLACK) {
+            emitted = emitted * power_heuristic(pdf, scene.lights.pdf_value(r.origin, r.direction, rec.point));
+        }
+        if photons.is_none() || specular_chain.unwrap_or(0) == 0 {
+            radiance += throughput * emitted;
+        }
+        if diffuse_bounces >= max_diffuse {
+            break;
+        }
+
+        //create variables to be passed to scatter() to be modified
+        let mut attenuation = Color::BLACK;
+        let mut r_scattered = Ray::new(Vec3::origin(), Vec3::origin());
+        if !scatter(rec.material, &r, &rec, &mut attenuation, &mut r_scattered, sampler) {
+            break;
+        }
+
+        attenuation = lambda.reflectance(attenuation);
+        //a dispersive interface splits the light, from here on the path only carries its own wavelength
+        if rec.material.is_dispersive() && !r.dispersed {
+            attenuation = attenuation * lambda.collapse();
+            r_scattered.dispersed = true;
+        }
+
+        bsdf_pdf = if rec.material.is_specular() {
+            specular_chain = specular_chain.map(|n| n + 1);
+            None
+        } else {
+            radiance += throughput * sample_lights(&r, &rec, scene, lambda, sampler);
+            if rec.material.is_volume() {
+                //photons aren't stored in volumes, the light they would bring comes from the path
+                specular_chain = None;
+            } else {
+                if let Some((map, radius)) = photons {
+                    radiance += throughput * gather_photons(&r, &rec, lambda, map, radius);
+                }
+                specular_chain = Some(0);
+            }
+            diffuse_bounces += 1;
+            if is_specular_sample(rec.material, &r, &rec, r_scattered.direction) {
+                None
+            } else {
+                Some(evaluate(rec.material, &r, &rec, r_scattered.direction).1)
+            }
+        };
+
+        throughput = throughput * attenuation;
+        r = r_scattered;
+
+        //paths carrying little light are ended at random, the survivors make up for them
+        if depth >= ROULETTE_DEPTH {
+            let (x, y, z) = throughput.get_components();
+            let survival = x.max(y).max(z).min(0.95);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
+
+    radiance
+}
+
+//direct light reaching rec from the analytic lights and a shadow ray towards a random emitter,
+//the latter weighted against bsdf sampling
+fn sample_lights(r: &Ray, rec: &HitRecord, scene: &Scene, lambda: &SampledWavelengths, sampler: &mut dyn Sampler) -> Color {
+    let direct = analytic_lights(r, rec, scene, lambda, sampler);
+
+    if scene.lights.is_empty() {
+        return direct;
+    }
+
+    let (light, direction) = scene.lights.random(rec.point, sampler);
+    let light_pdf = scene.lights.light_pdf(rec.point, light, direction);
+    if light_pdf <= 0.0 {
+        return direct;
+    }
+    let (f, bsdf_pdf) = evaluate(rec.material, r, rec, direction);
+    if bsdf_pdf <= 0.0 {
+        return direct;
+    }
+
+    let shadow_ray = r.bounce(rec.point, direction);
+    //only the light that was chosen counts, anything else in the way blocks it
+    let radiance = match scene.world.hit(&shadow_ray, 0.001, INFINITY) {
+        Some(light_rec) if scene.lights.find(rec.point, direction, light_rec.point) == Some(light) => {
+            emitted(light_rec.material, lambda) * transmittance(&shadow_ray, scene, lambda, light_rec.t * direction.length())
+        }
+        _ => Color::BLACK,
+    };
+
+    direct + lambda.reflectance(f) * radiance * power_heuristic(light_pdf, bsdf_pdf) / light_pdf
+}
+
+//density estimate of the light photons within radius of rec bring to it
+fn gather_photons(r: &Ray, rec: &HitRecord, lambda: &SampledWavelengths, map: &PhotonMap, radius: f64) -> Color {
+    let mut gathered = Color::BLACK;
+    map.query(rec.point, radius, &mut |photon| {
+        let cosine = dot(-photon.direction, rec.normal).abs();
+        if cosine > 1e-6 {
+            let (f, _) = evaluate(rec.material, r, rec, -photon.direction);
+            gathered += lambda.reflectance(f / cosine) * lambda.emission(&Spectrum::Rgb(photon.power));
+        }
+    });
+    gathered / (PI * radius * radius)
+}
+
+//direct light reaching rec from the point, spot and directional lights and the sun of the sky,
+//the latter weighted against rays leaving the scene finding its disk
+pub fn analytic_lights(r: &Ray, rec: &HitRecord, scene: &Scene, lambda: &SampledWavelengths, sampler: &mut dyn Sampler) -> Color {
+    let mut direct = Color::BLACK;
+    let sun = scene.sky.sun().map(|sun| (sun, true));
+    for (light, in_sky) in scene.analytic_lights.iter().map(|light| (light, false)).chain(sun) {
+        if let Some(sample) = light.sample(rec.point, lambda, sampler.get_2d()) {
+            let shadow_ray = r.bounce(rec.point, sample.direction);
+            if scene.world.hit(&shadow_ray, 0.001, sample.distance).is_none() {
+                let (f, bsdf_pdf) = evaluate(rec.material, r, rec, sample.direction);
+                let weight = if in_sky {power_heuristic(light.pdf(sample.direction), bsdf_pdf)} else {1.0};
+                let distance = sample.distance.min(SKY_DISTANCE);
+                direct += lambda.reflectance(f) * sample.radiance * transmittance(&shadow_ray, scene, lambda, distance) * weight;
+            }
+        }
+    }
+    direct
+}
+
+//light of the sky arriving along r, which the last bounce chose with density bsdf_pdf. The sun was
+//sampled directly there unless the bounce was specular, so its disk is weighted against that
+pub fn sky_radiance(r: &Ray, scene: &Scene, lambda: &SampledWavelengths, bsdf_pdf: Option<f64>) -> Color {
+    let radiance = scene.sky.radiance(r.direction, lambda);
+    match (scene.sky.sun(), bsdf_pdf) {
+        (Some(sun), Some(pdf)) => {
+            let disk = sun.radiance(r.direction, lambda);
+            radiance - disk * (1.0 - power_heuristic(pdf, sun.pdf(r.direction)))
+        }
+        _ => radiance,
+    }
+}
+
+//random direction around normal with density cos / pi, from two uniform numbers
+pub fn cosine_direction(normal: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+    let phi = 2.0 * PI * u1;
+    Onb::build_from_w(normal).local(Vec3::new(phi.cos() * u2.sqrt(), phi.sin() * u2.sqrt(), (1.0 - u2).sqrt()))
+}
+
+//fraction of light passing along distance of r through the medium it travels in
+pub fn transmittance(r: &Ray, scene: &Scene, lambda: &SampledWavelengths, distance: f64) -> Color {
+    match r.medium.or(scene.fog) {
+        Some(medium) => medium.sampled(lambda).transmittance(distance),
+        None => Color::WHITE,
+    }
+}
+
+//multiple importance sampling weight of a sample taken with pdf_a, when pdf_b could have taken it too
+fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a = pdf_a * pdf_a;
+    let b = pdf_b * pdf_b;
+    if a + b == 0.0 {0.0} else {a / (a + b)}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::light_sampler::{LightList, LightSelection};
+    use crate::material::{Material, Ior};
+    use crate::sampler::IndependentSampler;
+    use crate::sky::{Sky, PreethamSky};
+    use crate::sphere::Sphere;
+    use crate::light::Light;
+    use crate::medium::{Medium, Phase};
+    use raytracer::{with_random_source, Pcg32};
+
+    fn scene_with(spheres: Vec<Sphere>) -> Scene {
+        let world = spheres.into_iter().map(|s| Box::new(s) as Box<dyn Hittable + Send + Sync>).collect();
+        Scene {
+            world: HittableList::new(world),
+            lights: LightList::new(Vec::new(), LightSelection::Uniform),
+            analytic_lights: Vec::new(),
+            fog: None,
+            sky: Sky::Gradient,
+            spectral: false,
+        }
+    }
+
+    #[test]
+    fn ambient_occlusion() {
+        let gray = Material::Lambertian { albedo: Color::new(0.5, 0.5, 0.5) };
+        let integrator = AmbientOcclusionIntegrator { distance: 3.0 };
+        let mut sampler = IndependentSampler;
+        let mut film = Film::new(1, 1);
+        let r = Ray::new(Vec3::origin(), Vec3::new(0.0, 0.0, -1.0));
+
+        //nothing above a lone sphere, inside a closed one everything is blocked
+        let open = scene_with(vec![Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, gray.clone())]);
+        assert_eq!(integrator.li(&r, &open, &mut sampler, (0, 0), 0, &mut film), Color::WHITE);
+        let closed = scene_with(vec![Sphere::new(Vec3::origin(), 1.0, gray)]);
+        assert_eq!(integrator.li(&r, &closed, &mut sampler, (0, 0), 0, &mut film), Color::BLACK);
+    }
+
+    #[test]
+    fn debug_shows_normals() {
+        let scene = scene_with(vec![Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Lambertian { albedo: Color::WHITE })]);
+        let r = Ray::new(Vec3::origin(), Vec3::new(0.0, 0.0, -1.0));
+        let color = DebugIntegrator.li(&r, &scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1));
+        assert!((color - Color::new(0.5, 0.5, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn seeded_paths_are_repeatable() {
+        let scene = scene_with(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Lambertian { albedo: Color::new(0.8, 0.6, 0.4) }),
+            Sphere::new(Vec3::new(0.0, -101.0, -3.0), 100.0, Material::Lambertian { albedo: Color::new(0.5, 0.5, 0.5) }),
+        ]);
+        let integrator = PathIntegrator { max_depth: 8 };
+        let r = Ray::new(Vec3::origin(), Vec3::new(0.0, 0.0, -1.0));
+        let trace = |seed| {
+            with_random_source(Pcg32::new(seed, 0), || integrator.li(&r, &scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1))).1
+        };
+        assert_eq!(trace(1), trace(1));
+        assert_ne!(trace(1), trace(2));
+    }
+
+    #[test]
+    fn roulette_keeps_closed_box_unbiased() {
+        //a lamp of radius r in the middle of a gray sphere of radius 2r. the lamp covers a quarter of
+        //the cosine weighted directions at the wall, which gets a * (le / 4 + lw * 3 / 4) back, so
+        //lw = a * le / (4 - 3 * a). most of that light comes over bounces past ROULETTE_DEPTH
+        let albedo = 0.8;
+        let lamp = Sphere::new(Vec3::origin(), 0.5, Material::DiffuseLight { emission: Spectrum::Rgb(Color::WHITE) });
+        let mut scene = scene_with(vec![lamp.clone(), Sphere::new(Vec3::origin(), 1.0, Material::Lambertian { albedo: albedo * Color::WHITE })]);
+        scene.lights = LightList::new(vec![Box::new(lamp)], LightSelection::Uniform);
+        let expected = albedo / (4.0 - 3.0 * albedo);
+
+        let integrator = PathIntegrator { max_depth: 1000 };
+        let r = Ray::new(Vec3::new(0.0, 0.75, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let n = 20000;
+        let (_, sum) = with_random_source(Pcg32::new(7, 0), || {
+            (0..n).map(|_| integrator.li(&r, &scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1)).x()).sum::<f64>()
+        });
+        let mean = sum / n as f64;
+        assert!((mean - expected).abs() < 0.01, "{} {}", mean, expected);
+    }
+
+    #[test]
+    fn photon_mapping_matches_path_tracing_on_caustic() {
+        //glass ball focusing a lamp onto the floor below, all inside a black dome. rays looking at the
+        //floor around the focus take the passes in turn, as the samples of a pixel do
+        let lamp = Sphere::new(Vec3::new(0.0, 3.0, 0.0), 0.5, Material::DiffuseLight { emission: Spectrum::Rgb(10.0 * Color::WHITE) });
+        let glass = Material::Dielectric { albedo: Color::WHITE, index_of_refraction: Ior::Constant(1.5), film: None };
+        let mut scene = scene_with(vec![
+            lamp.clone(),
+            Sphere::new(Vec3::new(0.0, 1.0, 0.0), 0.5, glass),
+            Sphere::new(Vec3::new(0.0, -100.0, 0.0), 100.0, Material::Lambertian { albedo: 0.8 * Color::WHITE }),
+            Sphere::new(Vec3::origin(), 10.0, Material::Lambertian { albedo: Color::BLACK }),
+        ]);
+        scene.lights = LightList::new(vec![Box::new(lamp)], LightSelection::Uniform);
+
+        let (passes, rays) = (16, 40000);
+        let photons = with_random_source(Pcg32::new(1, 0), || PhotonMappingIntegrator::new(&scene, 8, passes, 20000, 0.1)).1;
+        let path = PathIntegrator { max_depth: 8 };
+        let origin = Vec3::new(0.0, 0.5, 2.0);
+        let mean = |integrator: &dyn Integrator| {
+            let (_, sum) = with_random_source(Pcg32::new(2, 0), || {
+                (0..rays).map(|k| {
+                    let target = Vec3::new(raytracer::random_in_range(-0.3, 0.3), 0.0, raytracer::random_in_range(-0.3, 0.3));
+                    let r = Ray::new(origin, target - origin);
+                    integrator.li(&r, &scene, &mut IndependentSampler, (0, 0), k % passes, &mut Film::new(1, 1)).x()
+                }).sum::<f64>()
+            });
+            sum / rays as f64
+        };
+        let (expected, estimate) = (mean(&path), mean(&photons));
+        assert!((estimate - expected).abs() < 0.05 * expected, "{} {}", estimate, expected);
+    }
+
+    #[test]
+    fn analytic_lights_reach_fog() {
+        //a point light a unit above a ray through fog inside a black dome. the light scattered once
+        //towards the ray is sigma_s * e^(-sigma_s * t) / (4 pi) * e^(-sigma_s * d) / d^2 summed along it
+        let sigma_s = 0.3;
+        let radius = 10.0;
+        let mut scene = scene_with(vec![Sphere::new(Vec3::origin(), radius, Material::Lambertian { albedo: Color::BLACK })]);
+        scene.fog = Some(Medium { sigma_a: Color::BLACK, sigma_s: sigma_s * Color::WHITE, phase: Phase::Isotropic });
+        scene.analytic_lights = vec![Light::Point { position: Vec3::new(0.0, 1.0, 0.0), intensity: Spectrum::Rgb(Color::WHITE) }];
+
+        let steps = 100000;
+        let expected = (0..steps).map(|k| {
+            let t = (k as f64 + 0.5) / steps as f64 * radius;
+            let d = (t * t + 1.0f64).sqrt();
+            sigma_s * (-sigma_s * t).exp() / (4.0 * PI) * (-sigma_s * d).exp() / (d * d)
+        }).sum::<f64>() * radius / steps as f64;
+
+        let integrator = DirectLightingIntegrator { max_depth: 8 };
+        let r = Ray::new(Vec3::origin(), Vec3::new(1.0, 0.0, 0.0));
+        let n = 20000;
+        let (_, sum) = with_random_source(Pcg32::new(3, 0), || {
+            (0..n).map(|_| integrator.li(&r, &scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1)).x()).sum::<f64>()
+        });
+        let mean = sum / n as f64;
+        assert!((mean - expected).abs() < 0.03 * expected, "{} {}", mean, expected);
+    }
+
+    #[test]
+    fn sun_lights_floor_once() {
+        //a floor under a black sky with only the sun, which can be sampled as a light and hit as a disk
+        let albedo = 0.5;
+        let mut scene = scene_with(vec![Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Lambertian { albedo: albedo * Color::WHITE })]);
+        scene.sky = Sky::Preetham(Box::new(PreethamSky::new(40.0, 0.0, 3.0, 0.0, 1.0)));
+        let lambda = SampledWavelengths::new(550.0, false);
+        let sun = scene.sky.sun().unwrap().sample(Vec3::origin(), &lambda, (0.5, 0.5)).unwrap();
+        let expected = albedo / PI * sun.radiance.y() * sun.direction.y();
+
+        let integrator = DirectLightingIntegrator { max_depth: 8 };
+        let r = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.3));
+        let n = 2000;
+        let (_, sum) = with_random_source(Pcg32::new(4, 0), || {
+            (0..n).map(|_| integrator.li(&r, &scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1)).y()).sum::<f64>()
+        });
+        let mean = sum / n as f64;
+        assert!((mean - expected).abs() < 0.01 * expected, "{} {}", mean, expected);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::ray::Ray;
+use crate::hittable::HitRecord;
+use raytracer::{PI, Pcg32, with_random_source};
+use crate::material::{Material, scatter, evaluate, is_specular_sample};
+use crate::microfacet::{TrowbridgeReitz, fresnel_dielectric, reflect_local, refract_local, dielectric_f, dielectric_pdf};
+use crate::onb::Onb;
+use crate::vec3::{Vec3, Color, unit_vector, dot};
+use crate::sampler::{Sampler, IndependentSampler, hash64};
+
+//bounces between the interface and the base before the path counts as absorbed
+const MAX_INTERNAL_BOUNCES: u32 = 16;
+
+//dielectric layer on top of a base material, like varnish or the clearcoat of car paint
+#[derive(Debug, Clone, Copy)]
+pub struct Coating {
+    pub ior: f64,
+    //0 gives a smooth coat
+    pub roughness: f64,
+    pub thickness: f64,
+    //absorption coefficient per unit thickness for each color channel
+    pub absorption: Color,
+}
+
+impl Coating {
+    //reflects or refracts at the interface, wo points away from it and lies above in the local frame.
+    //returns the new direction and the weight of the sample
+    fn scatter_interface(&self, wo: Vec3, eta: f64, sampler: &mut dyn Sampler) -> Option<(Vec3, f64)> {
+        let distrib = self.distribution();
+        let smooth = self.roughness == 0.0;
+        let (u1, u2) = sampler.get_2d();
+        let wm = if smooth {Vec3::new(0.0, 0.0, 1.0)} else {distrib.sample_wm(wo, u1, u2)};
+
+        let f = fresnel_dielectric(dot(wo, wm), eta);
+        let reflected = sampler.get_1d() < f;
+        let wi = if reflected {
+            reflect_local(wo, wm)
+        } else {
+            refract_local(wo, wm, eta)?
+        };
+
+        //a microfacet can turn the direction to the wrong side of the interface, which ends the path
+        if (wi.z() > 0.0) != reflected {
+            return None
+        }
+        //visible normal sampling leaves only the masking of the outgoing direction in the weight
+        let weight = if smooth {1.0} else {distrib.g(wo, wi) / distrib.g1(wo)};
+        Some((wi, weight))
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness, 0.0)
+    }
+
+    //density of light inside the coat travelling up along up leaving through a rough interface along wi,
+    //times the weight scatter_interface gives it
+    fn exit_density(&self, up: Vec3, wi: Vec3) -> f64 {
+        let distrib = self.distribution();
+        let wo = Vec3::new(-up.x(), -up.y(), up.z());
+        let wt = Vec3::new(wi.x(), wi.y(), -wi.z());
+        dielectric_pdf(&distrib, wo, wt, 1.0 / self.ior) * distrib.g(wo, wt) / distrib.g1(wo)
+    }
+
+    //direction inside the coat to gather the light leaving along wi from, and the factor turning the
+    //light the base sends along it into light leaving along wi
+    fn sample_exit(&self, wi: Vec3, sampler: &mut dyn Sampler) -> Option<(Vec3, f64)> {
+        if self.roughness == 0.0 {
+            //the only direction refracting into wi, with the change of solid angle across the interface
+            let up = -refract_local(wi, Vec3::new(0.0, 0.0, 1.0), self.ior)?;
+            let factor = (1.0 - fresnel_dielectric(wi.z(), self.ior)) * wi.z() / (self.ior * self.ior * up.z());
+            return Some((up, factor))
+        }
+        let distrib = self.distribution();
+        let (u1, u2) = sampler.get_2d();
+        let wm = distrib.sample_wm(wi, u1, u2);
+        let wt = refract_local(wi, wm, self.ior)?;
+        //only refraction is followed, so the choice between it and reflection is left out of the pdf
+        let transmitted = 1.0 - fresnel_dielectric(dot(wi, wm), self.ior);
+        if wt.z() >= 0.0 || transmitted <= 0.0 {
+            return None
+        }
+        let pdf = dielectric_pdf(&distrib, wi, wt, self.ior) / transmitted;
+        if pdf <= 0.0 {
+            return None
+        }
+        Some((-wt, self.exit_density(-wt, wi) / pdf))
+    }
+
+    //attenuation of light crossing the coat along w
+    fn transmittance(&self, w: Vec3) -> Color {
+        let distance = self.thickness / w.z().abs().max(1e-4);
+        Color::new(
+            (-self.absorption.x() * distance).exp(),
+            (-self.absorption.y() * distance).exp(),
+            (-self.absorption.z() * distance).exp(),
+        )
+    }
+
+    //random walk between the interface and the base until the path leaves the coat
+    pub fn scatter(
+            &self,
+            base: &Material,
+            r_in: &Ray,
+            rec: &HitRecord,
+            attenuation: &mut Color,
+            r_scattered: &mut Ray,
+            sampler: &mut dyn Sampler
+        ) -> bool {
+
+        //seen from the inside of the object only the base is there
+        if !rec.front_face {
+            return scatter(base, r_in, rec, attenuation, r_scattered, sampler)
+        }
+
+        let onb = Onb::build_from_w(rec.normal);
+        let wo = onb.world_to_local(-unit_vector(r_in.direction));
+
+        let (mut w, weight) = match self.scatter_interface(wo, self.ior, sampler) {
+            Some(sample) => sample,
+            None => return false,
+        };
+        let mut throughput = Color::WHITE * weight;
+
+        for _ in 0..MAX_INTERNAL_BOUNCES {
+            if w.z() > 0.0 {
+                //left through the top of the coat
+                *attenuation = throughput;
+                *r_scattered = r_in.bounce(rec.point, onb.local(w));
+                return true
+            }
+
+            throughput = throughput * self.transmittance(w);
+
+            let r_base = r_in.bounce(rec.point, onb.local(w));
+            let mut base_attenuation = Color::BLACK;
+            let mut base_scattered = r_base;
+            if !scatter(base, &r_base, rec, &mut base_attenuation, &mut base_scattered, sampler) {
+                return false
+            }
+            throughput = throughput * base_attenuation;
+
+            let up = onb.world_to_local(unit_vector(base_scattered.direction));
+            if up.z() <= 0.0 {
+                //transmitted by the base into the object
+                *attenuation = throughput;
+                *r_scattered = base_scattered;
+                return true
+            }
+            throughput = throughput * self.transmittance(up);
+
+            //the interface seen from below, mirrored so the incoming side lies above
+            let (wi, weight) = match self.scatter_interface(Vec3::new(-up.x(), -up.y(), up.z()), 1.0 / self.ior, sampler) {
+                Some(sample) => sample,
+                None => return false,
+            };
+            throughput = throughput * weight;
+            w = Vec3::new(wi.x(), wi.y(), -wi.z());
+        }
+
+        false
+    }
+
+    //scattering function times cosine towards direction and the pdf of scatter() choosing it. The light
+    //going through the coat comes from one random walk, seeded by the two directions so the same pair
+    //always gets the same value
+    pub fn evaluate(&self, base: &Material, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> (Color, f64) {
+        if !rec.front_face {
+            return evaluate(base, r_in, rec, direction)
+        }
+
+        let onb = Onb::build_from_w(rec.normal);
+        let wo = onb.world_to_local(-unit_vector(r_in.direction));
+        let wi = onb.world_to_local(unit_vector(direction));
+
+        //the reflection off a smooth coat is a mirror, left to scatter()
+        let mut f = Color::BLACK;
+        if self.roughness > 0.0 && wi.z() > 0.0 {
+            f += Color::WHITE * (dielectric_f(&self.distribution(), wo, wi, self.ior) * wi.z());
+        }
+        let (_, walk) = with_random_source(Pcg32::new(seed(wo, wi), 0), || {
+            self.walk(base, r_in, rec, wi, &mut IndependentSampler)
+        });
+        (f + walk, self.pdf(wo, wi))
+    }
+
+    //the light leaving along wi after entering the coat from r_in. Every visit of the base adds the light
+    //it sends towards wi, then the walk goes on as in scatter() until it leaves the coat
+    fn walk(&self, base: &Material, r_in: &Ray, rec: &HitRecord, wi: Vec3, sampler: &mut dyn Sampler) -> Color {
+        let onb = Onb::build_from_w(rec.normal);
+        let wo = onb.world_to_local(-unit_vector(r_in.direction));
+
+        let mut f = Color::BLACK;
+        let (mut w, weight) = match self.scatter_interface(wo, self.ior, sampler) {
+            Some((w, weight)) if w.z() < 0.0 => (w, weight),
+            //reflected off the top, which is evaluated on its own
+            _ => return f,
+        };
+        let mut throughput = Color::WHITE * weight;
+        let smooth = self.roughness == 0.0;
+
+        for _ in 0..MAX_INTERNAL_BOUNCES {
+            throughput = throughput * self.transmittance(w);
+            let r_base = r_in.bounce(rec.point, onb.local(w));
+
+            if wi.z() < 0.0 {
+                f += throughput * evaluate(base, &r_base, rec, onb.local(wi)).0;
+            } else if !base.is_specular() {
+                if let Some((up, factor)) = self.sample_exit(wi, sampler) {
+                    let (base_f, _) = evaluate(base, &r_base, rec, onb.local(up));
+                    f += throughput * base_f * self.transmittance(up) * factor;
+                }
+            }
+
+            let mut base_attenuation = Color::BLACK;
+            let mut base_scattered = r_base;
+            if !scatter(base, &r_base, rec, &mut base_attenuation, &mut base_scattered, sampler) {
+                break
+            }
+            let up = onb.world_to_local(unit_vector(base_scattered.direction));
+            if up.z() <= 0.0 {
+                break
+            }
+            throughput = throughput * base_attenuation * self.transmittance(up);
+
+            //a single direction from the base can only be joined to wi through a rough interface
+            if !smooth && wi.z() > 0.0 && is_specular_sample(base, &r_base, rec, base_scattered.direction) {
+                f += throughput * self.exit_density(up, wi);
+            }
+
+            let (wr, weight) = match self.scatter_interface(Vec3::new(-up.x(), -up.y(), up.z()), 1.0 / self.ior, sampler) {
+                Some((wr, weight)) if wr.z() > 0.0 => (wr, weight),
+                //left through the top, which the light gathered above accounts for
+                _ => break,
+            };
+            throughput = throughput * weight;
+            w = Vec3::new(wr.x(), wr.y(), -wr.z());
+        }
+
+        f
+    }
+
+    //approximate pdf of scatter() choosing wi, for weighting against light sampling: the reflection off the
+    //top of the coat, with the light entering it spread out as a cosine lobe
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let reflection = if self.roughness > 0.0 && wi.z() > 0.0 {
+            dielectric_pdf(&self.distribution(), wo, wi, self.ior)
+        } else {
+            0.0
+        };
+        reflection + (1.0 - fresnel_dielectric(wo.z(), self.ior)) * wi.z().abs() / PI
+    }
+
+    //whether scatter() chose direction from a mirror reflection of the coat or a single direction of the base
+    pub fn is_specular_sample(&self, base: &Material, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> bool {
+        if !rec.front_face {
+            return is_specular_sample(base, r_in, rec, direction)
+        }
+        if self.roughness > 0.0 {
+            return false
+        }
+        let onb = Onb::build_from_w(rec.normal);
+        let wo = onb.world_to_local(-unit_vector(r_in.direction));
+        let mirror = onb.local(reflect_local(wo, Vec3::new(0.0, 0.0, 1.0)));
+        dot(unit_vector(direction), mirror) > 1.0 - 1e-9 || base.is_specular()
+    }
+}
+
+//seed of the walk between wo and wi
+fn seed(wo: Vec3, wi: Vec3) -> u64 {
+    let values: Vec<u32> = [wo.x(), wo.y(), wo.z(), wi.x(), wi.y(), wi.z()].iter()
+        .flat_map(|v| {
+            let bits = v.to_bits();
+            [(bits >> 32) as u32, bits as u32]
+        })
+        .collect();
+    hash64(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    fn coated_white(coating: Coating) -> Color {
+        let base = Material::Lambertian { albedo: Color::WHITE };
+        let r_in = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.2, 0.1, -1.0));
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r_in, Vec3::new(0.0, 0.0, 1.0));
+
+        let n = 20000;
+        let mut sum = Color::BLACK;
+        for _ in 0..n {
+            let mut attenuation = Color::BLACK;
+            let mut r_scattered = r_in;
+            if coating.scatter(&base, &r_in, &rec, &mut attenuation, &mut r_scattered, &mut IndependentSampler) {
+                assert!(r_scattered.direction.z() > 0.0);
+                sum += attenuation;
+            }
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn clear_coat_conserves_energy() {
+        let coating = Coating { ior: 1.5, roughness: 0.0, thickness: 0.1, absorption: Color::BLACK };
+        let albedo = coated_white(coating);
+        assert!((albedo.x() - 1.0).abs() < 0.03, "{:?}", albedo);
+    }
+
+    #[test]
+    fn absorbing_coat_tints() {
+        let coating = Coating { ior: 1.5, roughness: 0.2, thickness: 1.0, absorption: Color::new(0.1, 0.5, 2.0) };
+        let albedo = coated_white(coating);
+        assert!(albedo.x() > albedo.y() && albedo.y() > albedo.z());
+    }
+
+    #[test]
+    fn evaluate_matches_scatter() {
+        let base = Material::Lambertian { albedo: Color::new(0.8, 0.5, 0.2) };
+        let r_in = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.4, 0.2, -1.0));
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r_in, Vec3::new(0.0, 0.0, 1.0));
+
+        for roughness in [0.0, 0.3] {
+            let coating = Coating { ior: 1.5, roughness, thickness: 0.2, absorption: Color::new(0.2, 0.4, 0.6) };
+
+            //the weight of the samples that don't come from the mirror of a smooth coat
+            let n = 100000;
+            let mut sampled = Color::BLACK;
+            for _ in 0..n {
+                let mut attenuation = Color::BLACK;
+                let mut r_scattered = r_in;
+                if coating.scatter(&base, &r_in, &rec, &mut attenuation, &mut r_scattered, &mut IndependentSampler)
+                    && !coating.is_specular_sample(&base, &r_in, &rec, r_scattered.direction) {
+                    sampled += attenuation;
+                }
+            }
+            let sampled = sampled / n as f64;
+
+            let n = 300;
+            let mut integral = Color::BLACK;
+            for i in 0..n {
+                for j in 0..n {
+                    let direction = Vec3::sample_unit_vector(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+                    integral += coating.evaluate(&base, &r_in, &rec, direction).0;
+                }
+            }
+            let integral = integral * 4.0 * PI / (n * n) as f64;
+            assert!((integral - sampled).length() < 0.02, "{} {:?} {:?}", roughness, integral, sampled);
+        }
+    }
+}
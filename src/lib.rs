@@ -1,16 +1,76 @@
-use std::{path::Path, fs::File};
+use std::any::Any;
+use std::cell::RefCell;
 use rand::Rng;
 
+//source of the numbers random_f64 returns, in place of the thread's rng
+pub trait RandomSource: Any {
+    //next number in [0, 1)
+    fn next_f64(&mut self) -> f64;
+}
+
+thread_local! {
+    static RANDOM_SOURCE: RefCell<Option<Box<dyn RandomSource>>> = RefCell::new(None);
+}
+
+//runs f with every random number on this thread taken from source, and gives the source back
+pub fn with_random_source<S: RandomSource, R>(source: S, f: impl FnOnce() -> R) -> (S, R) {
+    let previous = RANDOM_SOURCE.with(|cell| cell.borrow_mut().replace(Box::new(source)));
+    let result = f();
+    let source = RANDOM_SOURCE.with(|cell| std::mem::replace(&mut *cell.borrow_mut(), previous));
+    let source: Box<dyn Any> = source.expect("random source was removed while in use");
+    (*source.downcast::<S>().expect("random source was replaced while in use"), result)
+}
+
+//permuted congruential generator (O'Neill 2014, pcg32 XSH RR), small and fast to seed, so every
+//pixel sample can have its own
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 0x5851f42d4c957f2d;
+
+    //generator started at seed, on one of 2^63 sequences that don't overlap
+    pub fn new(seed: u64, sequence: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, increment: (sequence << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Pcg32::MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+}
+
+impl RandomSource for Pcg32 {
+    //53 random bits, the most an f64 below 1 can hold
+    fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 21) ^ (self.next_u32() as u64 >> 11);
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
+
 //handy rng helpers
 pub fn random_f64() -> f64 {
-    let mut rng = rand::thread_rng();
-    Rng::gen_range(&mut rng, 0.0..1.0)
+    RANDOM_SOURCE.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(source) => source.next_f64(),
+        None => {
+            let mut rng = rand::thread_rng();
+            Rng::gen_range(&mut rng, 0.0..1.0)
+        }
+    })
 }
 
 //return random float in range [min, max)
 pub fn random_in_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    Rng::gen_range(&mut rng, min..max)
+    min + (max - min) * random_f64()
 }
 
 //handy math helpers
@@ -58,6 +118,36 @@ mod tests {
         assert_eq!(result, 4.0)
     }
 
+    struct Counter(f64);
+
+    impl RandomSource for Counter {
+        fn next_f64(&mut self) -> f64 {
+            self.0 += 0.25;
+            self.0
+        }
+    }
+
+    #[test]
+    fn injected_random_source() {
+        let (counter, values) = with_random_source(Counter(0.0), || (random_f64(), random_in_range(2.0, 4.0)));
+        assert_eq!(values, (0.25, 3.0));
+        assert_eq!(counter.0, 0.5);
+        assert!(random_f64() < 1.0);
+    }
+
+    #[test]
+    fn pcg32_reference_sequence() {
+        //first outputs of the reference implementation's pcg32-demo, seeded with 42 on sequence 54
+        let mut rng = Pcg32::new(42, 54);
+        let values: Vec<u32> = (0..4).map(|_| rng.next_u32()).collect();
+        assert_eq!(values, [0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293]);
+
+        let (_, a) = with_random_source(Pcg32::new(7, 0), || (0..8).map(|_| random_f64()).collect::<Vec<f64>>());
+        let (_, b) = with_random_source(Pcg32::new(7, 0), || (0..8).map(|_| random_f64()).collect::<Vec<f64>>());
+        assert_eq!(a, b);
+        assert!(a.iter().all(|x| (0.0..1.0).contains(x)));
+    }
+
     #[test]
     fn deg_to_rad1() {
         let deg = 90.0;
@@ -0,0 +1,217 @@
+use raytracer::{random_f64, deg_to_rad, INFINITY, PI};
+use crate::vec3::{Vec3, Point3, Color, unit_vector, dot};
+use crate::spectrum::{Spectrum, SampledWavelengths};
+use crate::onb::Onb;
+
+//light source without a shape in the world, it can only be reached with shadow rays
+#[derive(Debug, Clone)]
+pub enum Light {
+    //emits intensity evenly in all directions
+    Point {
+        position: Point3,
+        intensity: Spectrum, },
+    //point light restricted to a cone, fading out between the falloff start and the cone's edge
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        intensity: Spectrum,
+        cos_total_width: f64,
+        cos_falloff_start: f64, },
+    //light arriving from far away, like the sun. direction points towards the light and
+    //a non-zero angular diameter in degrees gives soft shadows
+    Directional {
+        direction: Vec3,
+        irradiance: Spectrum,
+        angular_diameter: f64, },
+}
+
+//light arriving at a point from a sampled direction
+pub struct LightSample {
+    //unit vector towards the light
+    pub direction: Vec3,
+    pub distance: f64,
+    //incident radiance divided by the pdf of the direction
+    pub radiance: Color,
+}
+
+impl Light {
+    //spot light at position pointed at look_at, angles of the cone in degrees
+    pub fn spot(position: Point3, look_at: Point3, intensity: Spectrum, total_width: f64, falloff_start: f64) -> Light {
+        Light::Spot {
+            position,
+            direction: unit_vector(look_at - position),
+            intensity,
+            cos_total_width: deg_to_rad(total_width).cos(),
+            cos_falloff_start: deg_to_rad(falloff_start).cos(),
+        }
+    }
+
+    //light reaching point, u picks the direction for lights that cover a disk of the sky
+    pub fn sample(&self, point: Point3, lambda: &SampledWavelengths, (u1, u2): (f64, f64)) -> Option<LightSample> {
+        match *self {
+            Light::Point { position, ref intensity } => {
+                let (direction, distance) = towards(point, position);
+                let radiance = lambda.emission(intensity) / (distance * distance);
+                Some(LightSample { direction, distance, radiance })
+            }
+            Light::Spot { position, direction: spot_direction, ref intensity, cos_total_width, cos_falloff_start } => {
+                let (direction, distance) = towards(point, position);
+                let falloff = falloff(dot(-direction, spot_direction), cos_total_width, cos_falloff_start);
+                if falloff <= 0.0 {
+                    return None
+                }
+                let radiance = falloff * lambda.emission(intensity) / (distance * distance);
+                Some(LightSample { direction, distance, radiance })
+            }
+            Light::Directional { direction, ref irradiance, angular_diameter } => {
+                let radiance = lambda.emission(irradiance);
+                if angular_diameter <= 0.0 {
+                    return Some(LightSample { direction: unit_vector(direction), distance: INFINITY, radiance })
+                }
+                //uniform over the disk of the light, the pdf cancels with its radiance
+                let cos_theta_max = deg_to_rad(angular_diameter / 2.0).cos();
+                let z = 1.0 + u1 * (cos_theta_max - 1.0);
+                let phi = 2.0 * PI * u2;
+                let sin_theta = (1.0 - z * z).sqrt();
+                let direction = Onb::build_from_w(direction).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z));
+                Some(LightSample { direction, distance: INFINITY, radiance })
+            }
+        }
+    }
+
+    //radiance seen looking along direction, only lights covering a disk of the sky can be seen
+    pub fn radiance(&self, direction: Vec3, lambda: &SampledWavelengths) -> Color {
+        match *self {
+            Light::Directional { ref irradiance, angular_diameter, .. } if self.pdf(direction) > 0.0 => {
+                lambda.emission(irradiance) / disk_solid_angle(angular_diameter)
+            }
+            _ => Color::BLACK,
+        }
+    }
+
+    //density of sample() choosing direction, zero for lights that send light along single directions
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match *self {
+            Light::Directional { direction: towards_light, angular_diameter, .. } if angular_diameter > 0.0 => {
+                let cos_theta_max = deg_to_rad(angular_diameter / 2.0).cos();
+                if dot(unit_vector(direction), unit_vector(towards_light)) >= cos_theta_max {
+                    1.0 / disk_solid_angle(angular_diameter)
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    //light leaving the light in a random direction, for photon tracing. Gives its origin, the direction
+    //and the intensity towards it divided by the direction's density, None for lights at infinity
+    pub fn emit(&self, lambda: &SampledWavelengths) -> Option<(Point3, Vec3, Color)> {
+        match *self {
+            Light::Point { position, ref intensity } => {
+                Some((position, Vec3::random_unit_vector(), 4.0 * PI * lambda.emission(intensity)))
+            }
+            Light::Spot { position, direction: spot_direction, ref intensity, cos_total_width, cos_falloff_start } => {
+                //uniform over the cone
+                let z = 1.0 - random_f64() * (1.0 - cos_total_width);
+                let phi = 2.0 * PI * random_f64();
+                let sin_theta = (1.0 - z * z).sqrt();
+                let direction = Onb::build_from_w(spot_direction).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z));
+                let solid_angle = 2.0 * PI * (1.0 - cos_total_width);
+                Some((position, direction, falloff(z, cos_total_width, cos_falloff_start) * solid_angle * lambda.emission(intensity)))
+            }
+            Light::Directional { .. } => None,
+        }
+    }
+
+    //estimate of the light's power for choosing between lights, zero for lights at infinity
+    pub fn power(&self) -> f64 {
+        match *self {
+            Light::Point { ref intensity, .. } => 4.0 * PI * intensity.luminance(),
+            Light::Spot { ref intensity, cos_total_width, cos_falloff_start, .. } => {
+                2.0 * PI * (1.0 - (cos_total_width + cos_falloff_start) / 2.0) * intensity.luminance()
+            }
+            Light::Directional { .. } => 0.0,
+        }
+    }
+}
+
+//solid angle of a disk of the sky with the angular diameter in degrees
+fn disk_solid_angle(angular_diameter: f64) -> f64 {
+    2.0 * PI * (1.0 - deg_to_rad(angular_diameter / 2.0).cos())
+}
+
+//unit direction and distance from point to position
+fn towards(point: Point3, position: Point3) -> (Vec3, f64) {
+    let offset = position - point;
+    let distance = offset.length();
+    (offset / distance, distance)
+}
+
+//smooth transition from full intensity inside the falloff start to nothing outside the cone
+fn falloff(cos_theta: f64, cos_total_width: f64, cos_falloff_start: f64) -> f64 {
+    if cos_theta < cos_total_width {
+        return 0.0
+    }
+    if cos_theta >= cos_falloff_start {
+        return 1.0
+    }
+    let t = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white() -> SampledWavelengths {
+        SampledWavelengths::new(550.0, false)
+    }
+
+    #[test]
+    fn point_light_inverse_square() {
+        let light = Light::Point { position: Point3::new(0.0, 2.0, 0.0), intensity: Spectrum::Rgb(Color::WHITE) };
+        let sample = light.sample(Point3::origin(), &white(), (random_f64(), random_f64())).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, 2.0);
+        assert_eq!(sample.radiance, Color::new(0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let light = Light::spot(Point3::new(0.0, 1.0, 0.0), Point3::origin(), Spectrum::Rgb(Color::WHITE), 30.0, 20.0);
+        let center = light.sample(Point3::origin(), &white(), (random_f64(), random_f64())).unwrap();
+        assert_eq!(center.radiance, Color::WHITE);
+        //45 degrees off the axis is outside of the cone
+        assert!(light.sample(Point3::new(1.0, 0.0, 0.0), &white(), (random_f64(), random_f64())).is_none());
+        let edge = light.sample(Point3::new(deg_to_rad(25.0).tan(), 0.0, 0.0), &white(), (random_f64(), random_f64())).unwrap();
+        assert!(edge.radiance.x() > 0.0 && edge.radiance.x() < 1.0 / (1.0 + edge.direction.x().powi(2)));
+    }
+
+    #[test]
+    fn directional_light_disk() {
+        let light = Light::Directional { direction: Vec3::new(0.0, 1.0, 1.0), irradiance: Spectrum::Rgb(Color::WHITE), angular_diameter: 10.0 };
+        for _ in 0..1000 {
+            let sample = light.sample(Point3::origin(), &white(), (random_f64(), random_f64())).unwrap();
+            let angle = dot(sample.direction, unit_vector(Vec3::new(0.0, 1.0, 1.0))).acos();
+            assert!(angle <= deg_to_rad(5.0) + 1e-9);
+            assert_eq!(sample.radiance, Color::WHITE);
+            //the radiance divided by the pdf is what sample() gives
+            assert!((light.radiance(sample.direction, &white()) / light.pdf(sample.direction) - Color::WHITE).length() < 1e-9);
+        }
+        assert_eq!(light.radiance(Vec3::new(0.0, 1.0, 0.0), &white()), Color::BLACK);
+    }
+
+    #[test]
+    fn spot_light_emission() {
+        let light = Light::spot(Point3::new(0.0, 1.0, 0.0), Point3::origin(), Spectrum::Rgb(Color::WHITE), 30.0, 20.0);
+        let solid_angle = 2.0 * PI * (1.0 - deg_to_rad(30.0).cos());
+        for _ in 0..1000 {
+            let (origin, direction, intensity) = light.emit(&white()).unwrap();
+            assert_eq!(origin, Point3::new(0.0, 1.0, 0.0));
+            assert!(dot(direction, Vec3::new(0.0, -1.0, 0.0)) >= deg_to_rad(30.0).cos() - 1e-9);
+            assert!(intensity.x() <= solid_angle + 1e-9);
+        }
+        assert!(light.power() > 0.0);
+    }
+}
@@ -0,0 +1,392 @@
+use raytracer::INFINITY;
+use crate::hittable::{Hittable, HitRecord};
+use crate::ray::Ray;
+use crate::vec3::{Vec3, Point3};
+use crate::sampler::Sampler;
+
+//how the light to send a shadow ray to is chosen
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSelection {
+    Uniform,
+    //proportional to the power of each light
+    Power,
+    //by the estimated contribution to the shading point, found with a bvh over the lights
+    Bvh,
+}
+
+//emitters in the world that are sampled for direct lighting
+pub struct LightList {
+    lights: Vec<Box<dyn Hittable + Send + Sync>>,
+    selector: Selector,
+    //light paths start on emitters chosen by power, independently of any shading point
+    emission: AliasTable,
+    //tree over the lights, for choosing them by contribution and finding the one a point lies on
+    bvh: LightBvh,
+}
+
+enum Selector {
+    Uniform,
+    Power(AliasTable),
+    Bvh,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Box<dyn Hittable + Send + Sync>>, selection: LightSelection) -> LightList {
+        let powers: Vec<f64> = lights.iter().map(|light| light.power()).collect();
+        let emission = AliasTable::new(&powers);
+        let selector = match selection {
+            LightSelection::Uniform => Selector::Uniform,
+            LightSelection::Power => Selector::Power(AliasTable::new(&powers)),
+            LightSelection::Bvh => Selector::Bvh,
+        };
+        let bvh = LightBvh::new(&lights);
+        LightList { lights, selector, emission, bvh }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    //total power of the emitters, compared with that of the analytic lights when shooting photons
+    pub fn power(&self) -> f64 {
+        self.lights.iter().map(|light| light.power()).sum()
+    }
+
+    //probability of choosing light i for a shading point at origin
+    fn pmf(&self, origin: Point3, i: usize) -> f64 {
+        match self.selector {
+            Selector::Uniform => 1.0 / self.lights.len() as f64,
+            Selector::Power(ref table) => table.pmf(i),
+            Selector::Bvh => self.bvh.pmf(origin, i),
+        }
+    }
+
+    //light a ray from origin in direction reaches at point, None if point isn't on a light
+    pub fn find(&self, origin: Point3, direction: Vec3, point: Point3) -> Option<usize> {
+        let r = Ray::new(origin, direction);
+        self.bvh.find(point, &|i| {
+            self.lights[i].hit(&r, 0.001, INFINITY).is_some_and(|rec| (rec.point - point).length_squared() < 1e-12)
+        })
+    }
+
+    //solid angle density of random() choosing light and then direction from origin
+    pub fn light_pdf(&self, origin: Point3, light: usize, direction: Vec3) -> f64 {
+        self.pmf(origin, light) * self.lights[light].pdf_value(origin, direction)
+    }
+
+    //solid angle density of random() choosing direction from origin, which reaches a light at point
+    pub fn pdf_value(&self, origin: Point3, direction: Vec3, point: Point3) -> f64 {
+        match self.find(origin, direction, point) {
+            Some(light) => self.light_pdf(origin, light, direction),
+            None => 0.0,
+        }
+    }
+
+    //light for a shading point at origin and the direction towards a point on it, both chosen with
+    //numbers from sampler
+    pub fn random(&self, origin: Point3, sampler: &mut dyn Sampler) -> (usize, Vec3) {
+        let n = self.lights.len();
+        let u = sampler.get_1d();
+        let i = match self.selector {
+            Selector::Uniform => ((u * n as f64) as usize).min(n - 1),
+            Selector::Power(ref table) => table.sample(u),
+            Selector::Bvh => self.bvh.sample(origin, u),
+        };
+        (i, self.lights[i].random(origin, sampler.get_2d()))
+    }
+
+    //point light leaves from and its area density, counting the choice of the light
+    pub fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(HitRecord<'_>, f64)> {
+        if self.lights.is_empty() {
+            return None
+        }
+        let i = self.emission.sample(sampler.get_1d());
+        let rec = self.lights[i].sample_surface(sampler.get_2d(), sampler.get_1d())?;
+        Some((rec, self.emission.pmf(i) * self.lights[i].surface_pdf()))
+    }
+
+    //area density of sample_surface choosing point, which a ray from origin in direction hit
+    pub fn surface_pdf(&self, origin: Point3, direction: Vec3, point: Point3) -> f64 {
+        match self.find(origin, direction, point) {
+            Some(light) => self.emission.pmf(light) * self.lights[light].surface_pdf(),
+            None => 0.0,
+        }
+    }
+}
+
+//samples indices proportional to weights in constant time, built with Vose's method
+pub struct AliasTable {
+    probability: Vec<f64>,
+    alias: Vec<usize>,
+    pmf: Vec<f64>,
+}
+
+impl AliasTable {
+    //weights that are all zero are treated as equal
+    pub fn new(weights: &[f64]) -> AliasTable {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let pmf: Vec<f64> = if sum > 0.0 {
+            weights.iter().map(|w| w / sum).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        let mut probability = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        let mut scaled: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            probability[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        //whatever is left over is 1 up to rounding
+        AliasTable { probability, alias, pmf }
+    }
+
+    pub fn sample(&self, u: f64) -> usize {
+        let n = self.probability.len();
+        let x = u * n as f64;
+        let i = (x as usize).min(n - 1);
+        if x - (i as f64) < self.probability[i] {i} else {self.alias[i]}
+    }
+
+    pub fn pmf(&self, i: usize) -> f64 {
+        self.pmf[i]
+    }
+}
+
+struct LightNode {
+    min: Point3,
+    max: Point3,
+    power: f64,
+    parent: Option<usize>,
+    //children for inner nodes, the light's index for leaves
+    children: Option<(usize, usize)>,
+    light: usize,
+}
+
+//binary tree over the lights, traversed towards the children that likely contribute most
+pub struct LightBvh {
+    nodes: Vec<LightNode>,
+    //leaf node of every light
+    leaves: Vec<usize>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Box<dyn Hittable + Send + Sync>]) -> LightBvh {
+        let mut bvh = LightBvh { nodes: Vec::new(), leaves: vec![0; lights.len()] };
+        let mut indices: Vec<usize> = (0..lights.len()).collect();
+        if !indices.is_empty() {
+            bvh.build(lights, &mut indices, None);
+        }
+        bvh
+    }
+
+    fn build(&mut self, lights: &[Box<dyn Hittable + Send + Sync>], indices: &mut [usize], parent: Option<usize>) -> usize {
+        let node = self.nodes.len();
+        if indices.len() == 1 {
+            let light = indices[0];
+            let (min, max) = lights[light].bounding_box().expect("lights need a bounding box");
+            self.nodes.push(LightNode { min, max, power: lights[light].power(), parent, children: None, light });
+            self.leaves[light] = node;
+            return node;
+        }
+
+        //split at the median along the axis where the lights are spread the most
+        let center = |i: usize| {
+            let (min, max) = lights[i].bounding_box().expect("lights need a bounding box");
+            let (x, y, z) = (0.5 * (min + max)).get_components();
+            [x, y, z]
+        };
+        let (lo, hi) = indices.iter().fold(([f64::MAX; 3], [f64::MIN; 3]), |(lo, hi), &i| {
+            let c = center(i);
+            ([lo[0].min(c[0]), lo[1].min(c[1]), lo[2].min(c[2])], [hi[0].max(c[0]), hi[1].max(c[1]), hi[2].max(c[2])])
+        });
+        let axis = (0..3).max_by(|&a, &b| (hi[a] - lo[a]).total_cmp(&(hi[b] - lo[b]))).unwrap_or(0);
+        indices.sort_by(|&a, &b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+        self.nodes.push(LightNode { min: Point3::origin(), max: Point3::origin(), power: 0.0, parent, children: None, light: 0 });
+        let (left_indices, right_indices) = indices.split_at_mut(indices.len() / 2);
+        let left = self.build(lights, left_indices, Some(node));
+        let right = self.build(lights, right_indices, Some(node));
+
+        let (l, r) = (&self.nodes[left], &self.nodes[right]);
+        let min = Point3::new(l.min.x().min(r.min.x()), l.min.y().min(r.min.y()), l.min.z().min(r.min.z()));
+        let max = Point3::new(l.max.x().max(r.max.x()), l.max.y().max(r.max.y()), l.max.z().max(r.max.z()));
+        let power = l.power + r.power;
+        self.nodes[node] = LightNode { min, max, power, parent, children: Some((left, right)), light: 0 };
+        node
+    }
+
+    //estimated contribution of a node's lights at point, its power over the squared distance. There is
+    //no term for the orientation of the lights: spheres and rectangles emit on every side, so the
+    //directions they light always cover the whole sphere
+    fn importance(&self, node: usize, point: Point3) -> f64 {
+        let node = &self.nodes[node];
+        let center = 0.5 * (node.min + node.max);
+        let radius_squared = 0.25 * (node.max - node.min).length_squared();
+        node.power / (point - center).length_squared().max(radius_squared).max(1e-8)
+    }
+
+    //probability of going to the first child of node
+    fn left_probability(&self, node: usize, point: Point3) -> f64 {
+        let (left, right) = self.nodes[node].children.expect("inner node");
+        let (l, r) = (self.importance(left, point), self.importance(right, point));
+        if l + r > 0.0 {l / (l + r)} else {0.5}
+    }
+
+    //light reached by going down the tree from the root, u is rescaled at every node so that one
+    //number is enough for the whole way
+    pub fn sample(&self, point: Point3, mut u: f64) -> usize {
+        let mut node = 0;
+        while let Some((left, right)) = self.nodes[node].children {
+            let p = self.left_probability(node, point);
+            if u < p {
+                u /= p;
+                node = left;
+            } else {
+                u = ((u - p) / (1.0 - p)).min(1.0 - f64::EPSILON);
+                node = right;
+            }
+        }
+        self.nodes[node].light
+    }
+
+    //light whose bounding box holds point and that is_on accepts
+    pub fn find(&self, point: Point3, is_on: &dyn Fn(usize) -> bool) -> Option<usize> {
+        if self.nodes.is_empty() {
+            return None
+        }
+        self.find_below(0, point, is_on)
+    }
+
+    fn find_below(&self, node: usize, point: Point3, is_on: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let n = &self.nodes[node];
+        let eps = 1e-6;
+        let (x, y, z) = point.get_components();
+        let inside = n.min.x() - eps <= x && x <= n.max.x() + eps
+            && n.min.y() - eps <= y && y <= n.max.y() + eps
+            && n.min.z() - eps <= z && z <= n.max.z() + eps;
+        if !inside {
+            return None
+        }
+        match n.children {
+            Some((left, right)) => self.find_below(left, point, is_on).or_else(|| self.find_below(right, point, is_on)),
+            None => if is_on(n.light) {Some(n.light)} else {None},
+        }
+    }
+
+    pub fn pmf(&self, point: Point3, light: usize) -> f64 {
+        let mut pmf = 1.0;
+        let mut node = self.leaves[light];
+        while let Some(parent) = self.nodes[node].parent {
+            let p_left = self.left_probability(parent, point);
+            let (left, _) = self.nodes[parent].children.expect("inner node");
+            pmf *= if node == left {p_left} else {1.0 - p_left};
+            node = parent;
+        }
+        pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracer::random_f64;
+    use crate::sampler::IndependentSampler;
+    use crate::material::Material;
+    use crate::rectangle::RectangleXZ;
+    use crate::spectrum::Spectrum;
+    use crate::vec3::Color;
+
+    #[test]
+    fn alias_table_frequencies() {
+        let table = AliasTable::new(&[1.0, 0.0, 3.0, 6.0]);
+        let n = 100000;
+        let mut counts = [0; 4];
+        for k in 0..n {
+            counts[table.sample((k as f64 + 0.5) / n as f64)] += 1;
+        }
+        for (i, count) in counts.into_iter().enumerate() {
+            assert!((count as f64 / n as f64 - table.pmf(i)).abs() < 1e-3);
+        }
+        assert_eq!(table.pmf(2), 0.3);
+    }
+
+    #[test]
+    fn surface_sampling() {
+        let lights = LightList::new(panels(), LightSelection::Uniform);
+        for _ in 0..100 {
+            let (rec, pdf) = lights.sample_surface(&mut IndependentSampler).unwrap();
+            //the brighter panels are chosen more often, each side of a panel has its own density
+            let i = (rec.point.x() / 2.0) as usize;
+            assert!((pdf - (i + 1) as f64 / 55.0 / 2.0).abs() < 1e-9);
+            let origin = Point3::new(0.5, 0.0, 0.5);
+            assert!((lights.surface_pdf(origin, rec.point - origin, rec.point) - pdf).abs() < 1e-9);
+        }
+    }
+
+    fn panels() -> Vec<Box<dyn Hittable + Send + Sync>> {
+        (0..10).map(|i| {
+            let x = i as f64 * 2.0;
+            let emission = Spectrum::Rgb(Color::new(1.0, 1.0, 1.0) * (i + 1) as f64);
+            Box::new(RectangleXZ::new(x, x + 1.0, 0.0, 1.0, 5.0, Material::DiffuseLight { emission })) as Box<dyn Hittable + Send + Sync>
+        }).collect()
+    }
+
+    #[test]
+    fn light_bvh_pmf_sums_to_one() {
+        let lights = panels();
+        let bvh = LightBvh::new(&lights);
+        let point = Point3::new(3.0, 0.0, 0.5);
+        let total: f64 = (0..lights.len()).map(|i| bvh.pmf(point, i)).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        //sampling follows the pmf, and prefers the lights close by
+        let n = 100000;
+        let mut counts = vec![0; lights.len()];
+        for _ in 0..n {
+            counts[bvh.sample(point, random_f64())] += 1;
+        }
+        for (i, count) in counts.into_iter().enumerate() {
+            assert!((count as f64 / n as f64 - bvh.pmf(point, i)).abs() < 0.01);
+        }
+        assert!(bvh.pmf(point, 1) > bvh.pmf(point, 9));
+    }
+
+    #[test]
+    fn light_list_pdf_matches_sampling() {
+        //the mixture pdf integrates to one over the directions towards the lights
+        for selection in [LightSelection::Uniform, LightSelection::Power, LightSelection::Bvh] {
+            let list = LightList::new(panels(), selection);
+            let origin = Point3::new(3.0, 0.0, 0.5);
+            let n = 20000;
+            let mut estimate = 0.0;
+            for _ in 0..n {
+                let (light, direction) = list.random(origin, &mut IndependentSampler);
+                let pdf = list.light_pdf(origin, light, direction);
+                //the point the direction reaches leads back to the light that was chosen
+                let point = origin + direction;
+                assert_eq!(list.find(origin, direction, point), Some(light));
+                assert!((list.pdf_value(origin, direction, point) - pdf).abs() < 1e-9 * pdf);
+                estimate += 1.0 / pdf;
+            }
+            //the mean of 1/pdf over samples gives the solid angle covered by the lights
+            let solid_angle: f64 = panels().iter().map(|light| {
+                let mut sum = 0.0;
+                for _ in 0..n {
+                    sum += 1.0 / light.pdf_value(origin, light.random(origin, (random_f64(), random_f64())));
+                }
+                sum / n as f64
+            }).sum();
+            assert!((estimate / n as f64 - solid_angle).abs() / solid_angle < 0.05, "{:?}", selection);
+        }
+    }
+}
@@ -6,18 +6,48 @@ mod sphere;
 mod rectangle;
 mod camera;
 mod material;
+mod spectrum;
+mod rgb_spectrum;
+mod onb;
+mod microfacet;
+mod principled;
+mod layered;
+mod medium;
+mod constant_medium;
+mod scene;
+mod perlin;
+mod heterogeneous_medium;
+mod texture;
+mod thin_film;
+mod light;
+mod light_sampler;
+mod config;
+mod sky;
+mod sampler;
+mod integrator;
+mod film;
+mod bdpt;
+mod photon_map;
+mod mlt;
+mod aov;
+mod denoise;
+mod adaptive;
+mod filter;
+mod tiles;
 
-use raytracer::{INFINITY, random_f64};
-use crate::hittable::{Hittable};
-use crate::hittable_list::HittableList;
-use crate::material::{Material, scatter};
-use crate::vec3::{Vec3, Color, Point3, unit_vector, color_to_string,};
-use crate::ray::Ray;
-use crate::sphere::Sphere;
-use crate::rectangle::{RectangleXY, RectangleYZ, RectangleXZ};
+use raytracer::{Pcg32, with_random_source};
+use crate::vec3::{Vec3, Color, Point3, color_to_string,};
 use crate::camera::Camera;
+use crate::config::Config;
+use crate::integrator::Integrator;
+use crate::film::Film;
+use crate::aov::Aovs;
+use crate::adaptive::{PixelStats, heat_color};
+use crate::filter::Filter;
+use crate::tiles::{TileQueue, Framebuffer, TILE_SIZE};
 
-use std::sync::Arc;
+use std::sync::{Arc, Barrier, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 
 use std::fs::File;
@@ -30,71 +60,30 @@ pub const ASPECT_RATIO: f64 = 16.0 / 9.0;
 pub const IMAGE_HEIGHT: u32 = 512;
 pub const IMAGE_WIDTH: u32 = (IMAGE_HEIGHT as f64 * ASPECT_RATIO) as u32;
 pub const SAMPLES_PER_PIXEL: u32 = 5;
-pub const MAX_DEPTH: u32 = 5;
-pub const THREAD_N: u32 = 4;
-
-//returns a color if ray r hits anything in world, otherwise returns sky gradient color
-fn ray_color(r: &Ray, world: &HittableList, depth: u32) -> Color {
-
-    //handle recursion base case, i.e. depth is 0, no more reflections for rays
-    if depth <= 0 {return Color::BLACK;}
-
-    let op_rec = world.hit(r, 0.001, INFINITY);
-    
-    match op_rec {
-        
-        Some(rec) => {
-            //create variables to be passed to scatter() to be modified
-            let mut attenuation = Color::BLACK;
-            let mut r_scattered = Ray::new(Vec3::origin(), Vec3::origin());
-            
-            if scatter(&rec.material, r, &rec, &mut attenuation, &mut r_scattered) {
-                return attenuation * ray_color(&r_scattered, world, depth-1)
-            } else {
-                return Color::BLACK
-            }
-        }
-        
-        None => {
-            //no hit for ray, get sky color and return it
-            let unit_direction = unit_vector(r.direction);
-            let t = 0.5 * (unit_direction.y() + 1.0);
-
-            return (1.0 - t) * Color::new(1.0, 1.0 , 1.0) + t * Color::new(0.5, 0.7, 1.0)    
-        }
-    }
-     
-}
-
+pub const MAX_DEPTH: u32 = 64;
+//brings the physical sky's luminance and the sun's irradiance to the scene's units
+pub const SKY_SCALE: f64 = 0.12;
+pub const SUN_IRRADIANCE: f64 = 2.5;
+//how far the ambient occlusion integrator looks for occluders
+pub const AO_DISTANCE: f64 = 2.0;
+//photons traced for every pass of photon mapping, and the gather radius of the first one
+pub const PHOTONS_PER_PASS: usize = 200_000;
+pub const PHOTON_RADIUS: f64 = 0.1;
+//paths traced to normalize Metropolis light transport and pick where its chains start
+pub const MLT_BOOTSTRAP_SAMPLES: u32 = 100_000;
 
 fn main() {
 
-    //create world item list
-    let mut list: Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(1);
+        }
+        Ok(config) => config,
+    };
 
-    //define some materials
-    let material_green_metall = Material::Metallic { albedo: (Color::new(0.28,0.95,0.55)), fuzz: (0.0) };
-    let material_blue_metall = Material::Metallic { albedo: Color::new(0.5, 0.45, 0.75), fuzz: 0.2, };
-    let material_glass = Material::Dielectric { albedo: Color::new(0.8, 0.90, 0.81), index_of_refraction: (1.5) };
-    let material_blue = Material::Lambertian { albedo: Color::new(0.1, 0.2, 0.8) };
-    let material_pink_glass = Material::Dielectric { albedo: Color::new(0.99, 0.3, 0.8), index_of_refraction: 2.4 };
-    let material_ground = Material::Lambertian { albedo: Color::new(0.7,0.8,0.3) };
-    
-    //add spheres and rectangles to world item list
-    
-    list.push( Box::new( Sphere::new(Point3::new( 1.0, 2.5, 10.0), 2.5, material_green_metall ) ) );
-    list.push( Box::new( RectangleXZ::new(-100.0, 100.0, -100.0, 100.0, 0.0, material_blue_metall ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new( 0.0, 0.0,    -1.0), 0.5,   material_center ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new(-1.0, 0.0,    -1.0), 0.5,   material_left   ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new( 1.0, 0.0,    -1.0), 0.5,   material_right  ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new( -0.5,1.0,    -1.2), 0.5,   material_up     ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new( 1.3, 0.5,    -2.5), 0.8,   material_behind ) ) );
-    //list.push( Box::new( Sphere::new(Point3::new( 0.0, 0.0,    -0.25), 0.1,   material_pink_glass ) ) );
-    //list.push( Box::new( Rectangle_xy::new(-1.0, 1.0, 0.1, 0.6, -1.0, material_ground ) ) );
-    //list.push( Box::new( Rectangle_yz::new(0.0, 1.0, -1.0, 1.0, 0.0, material_center ) ) );
-    
-    //pass world into Atomic pointer to allow safe thread access
-    let world = Arc::new(HittableList::new(list));
+    //pass scene into Atomic pointer to allow safe thread access
+    let scene = Arc::new(scene::build(config.scene, &config));
 
     //camera
     let look_from = Point3::new(0.0,3.0,0.0);
@@ -105,10 +94,19 @@ fn main() {
     let focus_dist = (look_from - look_at).length();
     let camera = Camera::new(look_from, look_at, vup, vfov, ASPECT_RATIO, aperture, focus_dist);
 
+    let adaptive = config.adaptive_max_samples > 0;
+    //a progressive render with a pass count takes that many samples, one a pass
+    let max_samples = if config.passes > 0 {config.passes} else if adaptive {config.adaptive_max_samples} else {SAMPLES_PER_PIXEL};
+
+    //photons and bootstrap paths traced while setting up the integrator are drawn from the seed too
+    let (_, integrator) = with_random_source(Pcg32::new(config.seed as u64, 0), || {
+        integrator::create(config.integrator, MAX_DEPTH, AO_DISTANCE, config.large_step_probability, max_samples, camera, &scene)
+    });
+    let integrator: Arc<dyn Integrator + Send + Sync> = Arc::from(integrator);
+
     //rendering
 
-    //open output file for writing
-    //TODO: move file io to own function
+    //create the output directory before rendering, so a bad path fails early
     let path = Path::new(OUTPUT_FILENAME);
     let display = path.display();
 
@@ -117,78 +115,199 @@ fn main() {
         Ok(a) => a,
     };
 
-    let mut output_file = match File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why),
-        Ok(file) => file,
-    };
-
-    let image_header = format!("P3\n{IMAGE_WIDTH} {IMAGE_HEIGHT}\n255\n");
-    match output_file.write_all(image_header.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        Ok(_) => (),
-    }
-
     //rendering loop, shoot rays into world and color pixels accordingly
     //start timer here to measure rendering time
     let now = std::time::Instant::now();
 
-    let number_of_lines_per_thread = IMAGE_HEIGHT / THREAD_N;
-    let mut image_array = vec![vec![Color::BLACK; (number_of_lines_per_thread * IMAGE_WIDTH + (IMAGE_HEIGHT % THREAD_N)) as usize]; THREAD_N as usize];
-    let mut handles = Vec::with_capacity(THREAD_N as usize);
+    let filter_radius = if config.filter_radius > 0.0 {config.filter_radius} else {config.filter.default_radius()};
+    let filter = Arc::new(Filter::new(config.filter, filter_radius));
+
+    //progressive rendering goes over the whole image once for every sample, so it can be looked at
+    //between passes, until the pass count or until interrupted. otherwise a single pass takes every
+    //sample of a pixel
+    let endless = config.progressive && config.passes == 0;
+    let (passes, samples_per_pass) = match (config.progressive, endless) {
+        (false, _) => (1, max_samples),
+        (true, false) => (config.passes, 1),
+        (true, true) => (u32::MAX, 1),
+    };
+
+    //the image is split into tiles that the threads take from a shared queue, so none sits idle
+    //while another is left with the expensive part of the image
+    let thread_count = if config.threads > 0 {config.threads as usize} else {thread::available_parallelism().map_or(1, |n| n.get())};
+    let tiles = tiles::tiles(IMAGE_WIDTH, IMAGE_HEIGHT, TILE_SIZE);
+    let tiles_per_pass = tiles.len();
+    let tiles_total = tiles_per_pass * passes as usize;
+    let framebuffer = Arc::new(Framebuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT));
+    let tiles_done = Arc::new(AtomicUsize::new(0));
+    let queue = Arc::new(TileQueue::new(tiles.clone(), thread_count));
+    //the workers and this thread meet at the end of every pass, and again once the next one is set up
+    let barrier = Arc::new(Barrier::new(thread_count + 1));
+    //set between passes when a snapshot needs the light the workers splatted so far
+    let flush_film = Arc::new(AtomicBool::new(false));
+    let (film_sender, film_receiver) = mpsc::channel();
+    //only integrators that splat get films the size of the image
+    let splats = integrator.splats();
+    //the denoiser is guided by the aovs too
+    let collect_aovs = config.aovs || config.denoise > 0.0;
+    let mut handles = Vec::with_capacity(thread_count);
+
+    //the workers are started once and keep their film and aovs over every pass
+    for nth_thread in 0..thread_count {
+
+        let arc_scene = Arc::clone(&scene);
+        let arc_integrator = Arc::clone(&integrator);
+        let arc_filter = Arc::clone(&filter);
+        let arc_queue = Arc::clone(&queue);
+        let arc_framebuffer = Arc::clone(&framebuffer);
+        let arc_tiles_done = Arc::clone(&tiles_done);
+        let arc_barrier = Arc::clone(&barrier);
+        let arc_flush_film = Arc::clone(&flush_film);
+        let film_sender = film_sender.clone();
+        let noise_threshold = config.noise_threshold;
+        let sampler_kind = config.sampler;
+        let seed = config.seed;
 
-    for nth_thread in 0..THREAD_N {
-    
-        let arc_world = Arc::clone(&world);
-        
-        let remainder = if nth_thread == (THREAD_N-1) {IMAGE_HEIGHT % THREAD_N} else {0};
-
-        let start_index = nth_thread * number_of_lines_per_thread;
-        let end_index = (nth_thread + 1) * number_of_lines_per_thread + remainder;
-        
-        //new thread for every line in image
         handles.push(thread::spawn(move || {
-            let mut pixel_array: Vec<Color> = Vec::with_capacity((number_of_lines_per_thread * IMAGE_WIDTH) as usize);
-            for j in (start_index..end_index).rev() {
-                
-                let progress = (1.0 - j as f64 / end_index as f64) * 100.0;
-                if j % 4 == 0 && nth_thread == 0 {eprintln!("progress: {:.2}%", progress)};
-
-                for i in 0..IMAGE_WIDTH {
-                
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-
-                    for _ in 0..SAMPLES_PER_PIXEL {
-                        let u = (i as f64 + random_f64()) / (IMAGE_WIDTH-1) as f64;
-                        let v  = (j as f64 + random_f64()) / (IMAGE_HEIGHT-1) as f64;
-
-                        let r = camera.get_ray(u, v);
-                
-                        pixel_color += ray_color(&r, &arc_world, MAX_DEPTH);
+            let mut sampler = sampler::create(sampler_kind, max_samples, seed);
+            //light reaching the camera from paths started at the lights, anywhere on the image
+            let mut film = splats.then(|| Film::new(IMAGE_WIDTH, IMAGE_HEIGHT));
+            let mut no_film = Film::new(0, 0);
+            let mut aovs = collect_aovs.then(|| Aovs::new(IMAGE_WIDTH, IMAGE_HEIGHT));
+            for pass in 0..passes {
+                if pass > 0 {
+                    arc_barrier.wait();
+                    if let (Some(film), true) = (film.as_mut(), arc_flush_film.load(Ordering::Relaxed)) {
+                        film_sender.send(std::mem::replace(film, Film::new(IMAGE_WIDTH, IMAGE_HEIGHT))).unwrap();
                     }
-                    pixel_array.push(pixel_color);
-                }}
-            (pixel_array, nth_thread)
+                }
+                let target_samples = (pass + 1) * samples_per_pass;
+
+                while let Some(tile) = arc_queue.pop(nth_thread) {
+                    let mut pixels = arc_framebuffer.read_tile(&tile);
+                    for ((i, j), stats) in tile.pixels().zip(pixels.iter_mut()) {
+
+                        //with adaptive sampling pixels stop once converged, or at the maximum
+                        while stats.count() < target_samples && !(adaptive && stats.converged(noise_threshold)) {
+                            //each pixel sample has random numbers of its own, so the image doesn't depend on
+                            //which thread rendered what
+                            let index = stats.count();
+                            let rng = Pcg32::new(sampler::hash64(&[i, j, index, seed]), 1);
+                            with_random_source(rng, || {
+                                sampler.start_pixel_sample((i, j), index);
+                                let ((dx, dy), weight) = arc_filter.sample(sampler.get_2d());
+                                //pixel i covers film coordinates i / IMAGE_WIDTH up to (i + 1) / IMAGE_WIDTH, the
+                                //same mapping Camera::film_coordinates uses to splat light paths. dividing by
+                                //IMAGE_WIDTH - 1 instead, as before filtering, framed the view a pixel wider
+                                let u = (i as f64 + 0.5 + dx) / IMAGE_WIDTH as f64;
+                                let v  = (j as f64 + 0.5 + dy) / IMAGE_HEIGHT as f64;
+
+                                let r = camera.get_ray(u, v, &mut *sampler);
+                                let film = film.as_mut().unwrap_or(&mut no_film);
+                                stats.add(weight * arc_integrator.li(&r, &arc_scene, &mut *sampler, (i, j), index, film));
+                                if let Some(ref mut aovs) = aovs {
+                                    aovs.add_sample(i, j, index, &aov::first_hit(&r, &arc_scene, AO_DISTANCE, &mut *sampler));
+                                }
+                            });
+                        }
+                    }
+                    arc_framebuffer.write_tile(&tile, &pixels);
+
+                    let done = arc_tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if endless {
+                        let done_in_pass = done - pass as usize * tiles_per_pass;
+                        if done_in_pass.is_multiple_of(16) || done_in_pass == tiles_per_pass {
+                            eprintln!("progress of pass {}: {:.2}%", pass + 1, done_in_pass as f64 / tiles_per_pass as f64 * 100.0);
+                        }
+                    } else if done.is_multiple_of(16) || done == tiles_total {
+                        eprintln!("progress: {:.2}%", done as f64 / tiles_total as f64 * 100.0);
+                    }
+                }
+                arc_barrier.wait();
+            }
+            (film, aovs)
         }));
     }
 
+    let mut film = splats.then(|| Film::new(IMAGE_WIDTH, IMAGE_HEIGHT));
+    let mut last_snapshot = std::time::Instant::now();
+    for pass in 0..passes {
+        barrier.wait();
+        if pass + 1 == passes {
+            break;
+        }
+
+        //snapshots of the image so far replace the output, until the last pass writes the final image
+        let passes_due = config.snapshot_passes > 0 && (pass + 1).is_multiple_of(config.snapshot_passes);
+        let seconds_due = config.snapshot_seconds > 0.0 && last_snapshot.elapsed().as_secs_f64() >= config.snapshot_seconds;
+        let snapshot_due = passes_due || seconds_due;
+        //the framebuffer is read while the workers wait, before they start on the next pass
+        let pixels = if snapshot_due {framebuffer.pixels()} else {Vec::new()};
+        flush_film.store(snapshot_due && splats, Ordering::Relaxed);
+        queue.refill(tiles.clone());
+        barrier.wait();
+
+        if snapshot_due {
+            if let Some(ref mut film) = film {
+                for _ in 0..thread_count {
+                    film.add(&film_receiver.recv().expect("render thread panicked"));
+                }
+            }
+            //written next to the output and moved over it, so interrupting the render never leaves half an image
+            let snapshot = compose_image(&pixels, film.as_ref());
+            let snapshot_path = path.with_extension("ppm.part");
+            if let Err(why) = write_image(&snapshot_path, &snapshot, 2.0, SAMPLES_PER_PIXEL).and_then(|_| fs::rename(&snapshot_path, path)) {
+                panic!("couldn't write to {}: {}", display, why)
+            }
+            if endless {
+                eprintln!("wrote snapshot after pass {}", pass + 1);
+            } else {
+                eprintln!("wrote snapshot after pass {} of {}", pass + 1, passes);
+            }
+            last_snapshot = std::time::Instant::now();
+        }
+    }
+
+    let mut aovs = collect_aovs.then(|| Aovs::new(IMAGE_WIDTH, IMAGE_HEIGHT));
     for handle in handles {
-        let res_vec = handle.join();
-        let color_vec = res_vec.expect("thread did not return color vec");
-        let vec = color_vec.0;
-        let index = color_vec.1;
-        image_array[index as usize] = vec;
+        let (thread_film, thread_aovs) = handle.join().expect("render thread panicked");
+        if let (Some(film), Some(thread_film)) = (film.as_mut(), thread_film) {
+            film.add(&thread_film);
+        }
+        if let (Some(aovs), Some(thread_aovs)) = (aovs.as_mut(), thread_aovs) {
+            aovs.add(&thread_aovs);
+        }
     }
 
-    for t in (0..THREAD_N).rev() {
-        for element in &image_array[t as usize] {
-            let pixel_color = element;
-            let color_string = color_to_string(*pixel_color, 2.0, SAMPLES_PER_PIXEL);
-            let color_string = format!("{color_string}\n");
-            match output_file.write_all(color_string.as_bytes()) {
-                Err(why) => panic!("couldn't write to {}: {}", display, why),
-                Ok(_) => (),
+    let pixels = framebuffer.pixels();
+    let mut image = compose_image(&pixels, film.as_ref());
+    let heatmap: Vec<Color> = pixels.iter().map(|stats| heat_color(stats.count(), max_samples)).collect();
+
+    if config.heatmap {
+        let heatmap_path = path.with_file_name(format!("{}_samples.ppm", path.file_stem().unwrap().to_str().unwrap()));
+        if let Err(why) = write_image(&heatmap_path, &heatmap, 1.0, 1) {
+            panic!("couldn't write to {}: {}", heatmap_path.display(), why)
+        }
+    }
+
+    if let (true, Some(aovs)) = (config.denoise > 0.0, aovs.as_ref()) {
+        if config.raw {
+            let raw_path = path.with_file_name(format!("{}_raw.ppm", path.file_stem().unwrap().to_str().unwrap()));
+            if let Err(why) = write_image(&raw_path, &image, 2.0, SAMPLES_PER_PIXEL) {
+                panic!("couldn't write to {}: {}", raw_path.display(), why)
             }
         }
+        image = denoise::denoise(&image, aovs, IMAGE_WIDTH, IMAGE_HEIGHT, SAMPLES_PER_PIXEL, config.denoise);
+    }
+
+    if let Err(why) = write_image(path, &image, 2.0, SAMPLES_PER_PIXEL) {
+        panic!("couldn't write to {}: {}", display, why)
+    }
+
+    if let (true, Some(aovs)) = (config.aovs, aovs.as_ref()) {
+        if let Err(why) = aovs.write(path) {
+            panic!("couldn't write aovs next to {}: {}", display, why)
+        }
     }
 
     let elapsed_dur = now.elapsed();
@@ -199,4 +318,29 @@ fn main() {
 
     eprintln!("\nTime to render: {:02}:{:02}:{:05.02}", hours, mins, frac_sec);
     eprintln!("Wrote to {OUTPUT_FILENAME}");
+}
+
+//image with pixels summed over SAMPLES_PER_PIXEL samples, from the pixels rendered so far and the light
+//splatted onto film if the integrator has one, both counted from the lower left corner
+fn compose_image(pixels: &[PixelStats], film: Option<&Film>) -> Vec<Color> {
+    //splats add up over every camera sample taken, they are scaled as if each pixel took SAMPLES_PER_PIXEL
+    let total_samples: u64 = pixels.iter().map(|stats| stats.count() as u64).sum();
+    let film_scale = (IMAGE_WIDTH * IMAGE_HEIGHT * SAMPLES_PER_PIXEL) as f64 / total_samples.max(1) as f64;
+    pixels.iter().enumerate().map(|(k, stats)| {
+        let (i, j) = (k as u32 % IMAGE_WIDTH, k as u32 / IMAGE_WIDTH);
+        stats.mean() * SAMPLES_PER_PIXEL as f64 + film.map_or(Color::BLACK, |film| film_scale * film.pixel(i, j))
+    }).collect()
+}
+
+//writes an image with pixels summed over samples_per_pixel and counted from the lower left corner as ppm
+fn write_image(path: &Path, image: &[Color], gamma: f64, samples_per_pixel: u32) -> std::io::Result<()> {
+    let mut output_file = std::io::BufWriter::new(File::create(path)?);
+    write!(output_file, "P3\n{IMAGE_WIDTH} {IMAGE_HEIGHT}\n255\n")?;
+    for j in (0..IMAGE_HEIGHT).rev() {
+        for i in 0..IMAGE_WIDTH {
+            let color_string = color_to_string(image[(j * IMAGE_WIDTH + i) as usize], gamma, samples_per_pixel);
+            writeln!(output_file, "{color_string}")?;
+        }
+    }
+    output_file.flush()
 }
\ No newline at end of file
@@ -1,19 +1,107 @@
-use raytracer::{clamp, random_f64};
+use raytracer::{clamp, PI};
 use crate::ray::Ray;
 use crate::hittable::HitRecord;
 use crate::vec3::{Vec3, Color, reflect, unit_vector, dot, refract};
+use crate::spectrum::{Spectrum, SampledWavelengths};
+use crate::principled::Principled;
+use crate::onb::Onb;
+use crate::layered::Coating;
+use crate::medium::{Medium, Phase};
+use crate::thin_film::{ThinFilm, Substrate};
+use crate::sampler::Sampler;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 #[derive(Debug)]
 pub enum Material {
     Lambertian { albedo: Color, },
     Metallic {
         albedo: Color,
         fuzz: f64,
+        film: Option<ThinFilm>,
     },
     Dielectric { 
         albedo: Color,
-        index_of_refraction: f64, },
+        index_of_refraction: Ior,
+        film: Option<ThinFilm>, },
+    DiffuseLight { emission: Spectrum },
+    Principled(Principled),
+    Layered { base: Box<Material>, coating: Coating },
+    //smooth dielectric boundary of a closed object filled with a scattering medium
+    Subsurface {
+        albedo: Color,
+        mean_free_path: Color,
+        ior: f64, },
+    //scatters light inside a ConstantMedium
+    Volume {
+        albedo: Color,
+        phase: Phase, },
+}
+
+//index of refraction, optionally depending on the wavelength (in nm) of the ray
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum Ior {
+    Constant(f64),
+    //n = a + b / lambda^2, with lambda in micrometers
+    Cauchy { a: f64, b: f64 },
+    //n^2 = 1 + sum of b_i * lambda^2 / (lambda^2 - c_i), with lambda in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+    pub const WATER: Ior = Ior::Cauchy { a: 1.3238, b: 0.00314 };
+
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let lambda = wavelength / 1000.0;
+        let lambda2 = lambda * lambda;
+
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * lambda2 / (lambda2 - c[i])).sum::<f64>();
+                n2.sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+impl Material {
+    //whether scattering off this material depends on the wavelength of the ray
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Dielectric { index_of_refraction, .. } => index_of_refraction.is_dispersive(),
+            Material::Layered { base, .. } => base.is_dispersive(),
+            _ => false,
+        }
+    }
+
+    //scatters only into single directions, which can't be sampled towards lights
+    pub fn is_specular(&self) -> bool {
+        match self {
+            Material::Metallic { fuzz, .. } => *fuzz <= 0.0,
+            Material::Layered { base, coating } => coating.roughness == 0.0 && base.is_specular(),
+            Material::Dielectric { .. } | Material::Subsurface { .. } | Material::DiffuseLight { .. } => true,
+            Material::Lambertian { .. } | Material::Principled(_) | Material::Volume { .. } => false,
+        }
+    }
+
+    //scatters inside a medium, there is no surface to take a cosine with
+    pub fn is_volume(&self) -> bool {
+        matches!(self, Material::Volume { .. })
+    }
 }
 
 pub fn scatter(
@@ -21,50 +109,213 @@ pub fn scatter(
         r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
-        r_scattered: &mut Ray
+        r_scattered: &mut Ray,
+        sampler: &mut dyn Sampler
     ) -> bool {
     
-    match material {
-        &Material::Lambertian { albedo } => {
-            let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+    match *material {
+        Material::Lambertian { albedo } => {
+            let mut scatter_direction = rec.normal + Vec3::sample_unit_vector(sampler.get_2d());
 
             if scatter_direction.near_zero() {
                 scatter_direction = rec.normal;
             }
 
-            *r_scattered = Ray::new(rec.point, scatter_direction);
+            *r_scattered = r_in.bounce(rec.point, scatter_direction);
             *attenuation = albedo;
             true
         }
-        &Material::Metallic { albedo, fuzz} => {
-            let reflected = reflect(unit_vector(r_in.direction), rec.normal);
-            let reflected = reflected + clamp(fuzz,0.0,1.0) * Vec3::random_in_unit_sphere();
-            *r_scattered = Ray::new(rec.point, reflected);
-            *attenuation = albedo;
+        Material::Metallic { albedo, fuzz, ref film } => {
+            let unit_dir = unit_vector(r_in.direction);
+            let reflected = reflect(unit_dir, rec.normal);
+            let reflected = reflected + clamp(fuzz,0.0,1.0) * Vec3::sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d());
+            *r_scattered = r_in.bounce(rec.point, reflected);
+            *attenuation = metal_reflectance(albedo, film, r_in, rec);
 
             dot(r_scattered.direction, rec.normal) > 0.0
         }
-        &Material::Dielectric { albedo, index_of_refraction } => {
+        Material::Dielectric { albedo, index_of_refraction, ref film } => {
 
-            let refraction_ratio = if rec.front_face {1.0 / index_of_refraction} else {index_of_refraction};
+            let ior = index_of_refraction.at(r_in.wavelength);
+            let refraction_ratio = if rec.front_face {1.0 / ior} else {ior};
             let unit_dir = unit_vector(r_in.direction);
             
             let cos_theta = dot(-unit_dir, rec.normal).min(1.0);
             let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
 
+            if let Some(film) = film {
+                //the film tints reflection and transmission differently, so the choice between them
+                //is made by the mean reflectance and the weights make up for it
+                let (n1, substrate) = if rec.front_face {(1.0, ior)} else {(ior, 1.0)};
+                let film_reflectance = film.reflectance(r_in, rec.point, cos_theta, n1, Substrate::Dielectric(substrate));
+                let p_reflect = ((film_reflectance.x() + film_reflectance.y() + film_reflectance.z()) / 3.0).clamp(1e-4, 1.0);
+                if p_reflect > sampler.get_1d() {
+                    *attenuation = albedo * film_reflectance / p_reflect;
+                    *r_scattered = r_in.bounce(rec.point, reflect(unit_dir, rec.normal));
+                } else {
+                    *attenuation = albedo * (Color::WHITE - film_reflectance) / (1.0 - p_reflect);
+                    *r_scattered = r_in.bounce(rec.point, refract(unit_dir, unit_vector(rec.normal), refraction_ratio));
+                }
+                return true
+            }
+
             let cannot_refract = refraction_ratio*sin_theta > 1.0; 
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_f64() {
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
                 let reflected = reflect(unit_dir, rec.normal);
                 *attenuation = albedo;
-                *r_scattered = Ray::new(rec.point, reflected);
-                return true
+                *r_scattered = r_in.bounce(rec.point, reflected);
             } else {
                 let refracted = refract(unit_dir, unit_vector(rec.normal), refraction_ratio);
                 *attenuation = albedo;
-                *r_scattered = Ray::new(rec.point, refracted);
-                return true
+                *r_scattered = r_in.bounce(rec.point, refracted);
+            }
+            true
+        }
+        Material::DiffuseLight { .. } => false,
+        Material::Principled(principled) => {
+            let onb = Onb::build_from_w(rec.normal);
+            let wo = onb.world_to_local(-unit_vector(r_in.direction));
+
+            match principled.sample(wo, rec.front_face, sampler) {
+                Some((wi, weight)) => {
+                    *attenuation = weight;
+                    *r_scattered = r_in.bounce(rec.point, onb.local(wi));
+                    true
+                }
+                None => false,
+            }
+        }
+        Material::Layered { ref base, coating } => coating.scatter(base, r_in, rec, attenuation, r_scattered, sampler),
+        Material::Volume { albedo, phase } => {
+            *r_scattered = r_in.bounce(rec.point, phase.sample(r_in.direction, sampler.get_2d()));
+            *attenuation = albedo;
+            true
+        }
+        Material::Subsurface { albedo, mean_free_path, ior } => {
+
+            let refraction_ratio = if rec.front_face {1.0 / ior} else {ior};
+            let unit_dir = unit_vector(r_in.direction);
+
+            let cos_theta = dot(-unit_dir, rec.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+
+            let cannot_refract = refraction_ratio*sin_theta > 1.0;
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+                //stays on its side of the boundary, keeping the medium it travels in
+                *r_scattered = r_in.bounce(rec.point, reflect(unit_dir, rec.normal));
+            } else {
+                let refracted = refract(unit_dir, unit_vector(rec.normal), refraction_ratio);
+                *r_scattered = r_in.bounce(rec.point, refracted);
+                //entering starts the random walk inside, leaving ends it
+                r_scattered.medium = if rec.front_face {
+                    Some(Medium::from_albedo(albedo, mean_free_path))
+                } else {
+                    None
+                };
+            }
+            *attenuation = Color::WHITE;
+            true
+        }
+    }
+}
+
+//light given off by the material, in the representation carried by the path
+//for materials that aren't specular, the scattering function times cosine towards direction
+//and the pdf of scatter() choosing it
+pub fn evaluate(material: &Material, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> (Color, f64) {
+    match *material {
+        Material::Lambertian { albedo } => {
+            let cosine = dot(unit_vector(direction), rec.normal);
+            if cosine <= 0.0 {
+                return (Color::BLACK, 0.0)
+            }
+            (albedo * cosine / PI, cosine / PI)
+        }
+        Material::Principled(principled) => {
+            let onb = Onb::build_from_w(rec.normal);
+            let wo = onb.world_to_local(-unit_vector(r_in.direction));
+            let wi = onb.world_to_local(unit_vector(direction));
+            (principled.eval(wo, wi, rec.front_face), principled.pdf(wo, wi, rec.front_face))
+        }
+        Material::Metallic { albedo, fuzz, ref film } if fuzz > 0.0 => {
+            if dot(direction, rec.normal) <= 0.0 {
+                return (Color::BLACK, 0.0)
             }
+            let reflected = reflect(unit_vector(r_in.direction), rec.normal);
+            let pdf = fuzz_pdf(reflected, direction, clamp(fuzz, 0.0, 1.0));
+            (metal_reflectance(albedo, film, r_in, rec) * pdf, pdf)
+        }
+        Material::Layered { ref base, coating } => coating.evaluate(base, r_in, rec, direction),
+        Material::Volume { albedo, phase } => {
+            let pdf = phase.pdf(r_in.direction, direction);
+            (albedo * pdf, pdf)
         }
+        _ => (Color::BLACK, 0.0),
+    }
+}
+
+//whether scatter() chose direction from a lobe holding a single direction, which evaluate() leaves out
+pub fn is_specular_sample(material: &Material, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> bool {
+    match material {
+        Material::Layered { base, coating } => coating.is_specular_sample(base, r_in, rec, direction),
+        _ => material.is_specular(),
+    }
+}
+
+//color of a metal's reflection, tinted by its film
+fn metal_reflectance(albedo: Color, film: &Option<ThinFilm>, r_in: &Ray, rec: &HitRecord) -> Color {
+    match film {
+        Some(film) => {
+            let cos_theta = dot(-unit_vector(r_in.direction), rec.normal).clamp(0.0, 1.0);
+            film.reflectance(r_in, rec.point, cos_theta, 1.0, Substrate::Conductor(albedo))
+        }
+        None => albedo,
+    }
+}
+
+//density of the direction towards a point uniform in the ball of radius fuzz around the unit vector
+//reflected, which is how fuzzy metals scatter
+fn fuzz_pdf(reflected: Vec3, direction: Vec3, fuzz: f64) -> f64 {
+    //the line along direction enters the ball at t0 and leaves it at t1, the volume in between grows as t^2
+    let b = dot(unit_vector(direction), reflected);
+    let discriminant = b * b - (1.0 - fuzz * fuzz);
+    if discriminant <= 0.0 {
+        return 0.0
+    }
+    let t1 = b + discriminant.sqrt();
+    let t0 = (b - discriminant.sqrt()).max(0.0);
+    if t1 <= 0.0 {
+        return 0.0
+    }
+    (t1.powi(3) - t0.powi(3)) / (4.0 * PI * fuzz.powi(3))
+}
+
+pub fn emitted(material: &Material, lambda: &SampledWavelengths) -> Color {
+    match material {
+        Material::DiffuseLight { emission } => lambda.emission(emission),
+        _ => Color::BLACK,
+    }
+}
+
+//overall color of the material's reflection or transmission, for the albedo pass
+pub fn albedo(material: &Material) -> Color {
+    match *material {
+        Material::Lambertian { albedo }
+        | Material::Metallic { albedo, .. }
+        | Material::Dielectric { albedo, .. }
+        | Material::Subsurface { albedo, .. }
+        | Material::Volume { albedo, .. } => albedo,
+        Material::Principled(principled) => principled.base_color,
+        Material::Layered { ref base, .. } => albedo(base),
+        Material::DiffuseLight { .. } => Color::WHITE,
+    }
+}
+
+//luminance of the light the material emits in rgb
+pub fn emitted_luminance(material: &Material) -> f64 {
+    match material {
+        Material::DiffuseLight { emission } => emission.luminance(),
+        _ => 0.0,
     }
 }
 
@@ -72,5 +323,80 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     //Schlick approximation for reflectance
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0 = r0 * r0;
-    r0 + (1.0 + r0)*(1.0 - cosine).powi(5)
-}
\ No newline at end of file
+    r0 + (1.0 - r0)*(1.0 - cosine).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ior_constant() {
+        let ior = Ior::Constant(1.5);
+        assert_eq!(ior.at(400.0), ior.at(700.0));
+        assert!(!ior.is_dispersive());
+    }
+
+    #[test]
+    fn ior_bk7() {
+        let n_d = Ior::BK7.at(587.6);
+        assert!((n_d - 1.5168).abs() < 1e-3);
+        assert!(Ior::BK7.at(450.0) > Ior::BK7.at(650.0));
+    }
+
+    #[test]
+    fn ior_diamond() {
+        let n_d = Ior::DIAMOND.at(587.6);
+        assert!((n_d - 2.417).abs() < 5e-3);
+    }
+
+    #[test]
+    fn ior_water() {
+        let n_d = Ior::WATER.at(587.6);
+        assert!((n_d - 1.333).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reflectance_bounds() {
+        assert!((reflectance(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert!((reflectance(0.0, 1.5) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fuzzy_metal_evaluate_matches_scatter() {
+        use crate::sampler::IndependentSampler;
+        let material = Material::Metallic { albedo: Color::new(0.9, 0.6, 0.3), fuzz: 0.4, film: None };
+        let r_in = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.5, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r_in, Vec3::new(0.0, 0.0, 1.0));
+
+        //the weights scatter() gives, with absorbed samples counting as black
+        let n = 100000;
+        let mut sampled = Color::BLACK;
+        for _ in 0..n {
+            let mut attenuation = Color::BLACK;
+            let mut r_scattered = r_in;
+            if scatter(&material, &r_in, &rec, &mut attenuation, &mut r_scattered, &mut IndependentSampler) {
+                sampled += attenuation;
+            }
+        }
+        let sampled = sampled / n as f64;
+
+        let n = 400;
+        let mut integral = Color::BLACK;
+        let mut pdf_integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let direction = Vec3::sample_unit_vector(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+                let (f, pdf) = evaluate(&material, &r_in, &rec, direction);
+                integral += f;
+                pdf_integral += pdf;
+            }
+        }
+        let integral = integral * 4.0 * PI / (n * n) as f64;
+        let pdf_integral = pdf_integral * 4.0 * PI / (n * n) as f64;
+        assert!((integral - sampled).length() < 0.01, "{:?} {:?}", integral, sampled);
+        //the directions below the surface are the absorbed ones
+        assert!((pdf_integral - sampled.x() / 0.9).abs() < 0.01, "{} {:?}", pdf_integral, sampled);
+    }
+}
@@ -0,0 +1,184 @@
+use raytracer::PI;
+use crate::vec3::{Vec3, Color, unit_vector, dot};
+use crate::spectrum::SampledWavelengths;
+use crate::onb::Onb;
+
+//distribution of directions light is scattered into inside a medium
+#[derive(Debug, Copy, Clone)]
+pub enum Phase {
+    Isotropic,
+    //g in (-1, 1) is the mean cosine, positive values scatter forward
+    HenyeyGreenstein { g: f64 },
+}
+
+impl Phase {
+    //new direction for light travelling along direction, from two numbers in [0, 1)
+    pub fn sample(&self, direction: Vec3, (u, u2): (f64, f64)) -> Vec3 {
+        match *self {
+            Phase::Isotropic => Vec3::sample_unit_vector((u, u2)),
+            Phase::HenyeyGreenstein { g } => {
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * u
+                } else {
+                    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+                    ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * u2;
+
+                let onb = Onb::build_from_w(unit_vector(direction));
+                onb.local(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+            }
+        }
+    }
+
+    //density of sample() turning light travelling along direction into wi, which is also the value
+    //of the phase function
+    pub fn pdf(&self, direction: Vec3, wi: Vec3) -> f64 {
+        match *self {
+            Phase::HenyeyGreenstein { g } if g.abs() >= 1e-3 => {
+                let cos_theta = dot(unit_vector(direction), unit_vector(wi));
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+            }
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+//absorbing and scattering medium a ray travels through, coefficients are per unit of distance
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub phase: Phase,
+}
+
+impl Medium {
+    //medium with the given single scattering albedo and mean free path per color channel
+    pub fn from_albedo(albedo: Color, mean_free_path: Color) -> Medium {
+        let sigma_t = Color::WHITE / mean_free_path;
+        Medium {
+            sigma_a: sigma_t * (Color::WHITE - albedo),
+            sigma_s: sigma_t * albedo,
+            phase: Phase::Isotropic,
+        }
+    }
+
+    pub fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    //brings the coefficients into the path's representation
+    pub fn sampled(&self, lambda: &SampledWavelengths) -> Medium {
+        Medium {
+            sigma_a: lambda.reflectance(self.sigma_a),
+            sigma_s: lambda.reflectance(self.sigma_s),
+            phase: self.phase,
+        }
+    }
+
+    //samples a free-flight distance along a ray that leaves the medium after distance_max.
+    //returns the distance if the ray scatters before that, and the weight of the sample.
+    //u1 picks the channel driving the distance and u2 the distance, the pdf averages all three
+    pub fn sample_distance(&self, distance_max: f64, (u1, u2): (f64, f64)) -> (Option<f64>, Color) {
+        let (tx, ty, tz) = self.sigma_t().get_components();
+        let sigma_t = [tx, ty, tz];
+        let channel = ((u1 * 3.0) as usize).min(2);
+
+        let distance = if sigma_t[channel] > 0.0 {
+            -(1.0 - u2).ln() / sigma_t[channel]
+        } else {
+            f64::INFINITY
+        };
+
+        if distance < distance_max {
+            let tr = transmittance(sigma_t, distance);
+            let pdf = (0..3).map(|i| sigma_t[i] * tr[i]).sum::<f64>() / 3.0;
+            let tr = Color::new(tr[0], tr[1], tr[2]);
+            (Some(distance), self.sigma_s * tr / pdf)
+        } else {
+            let tr = transmittance(sigma_t, distance_max);
+            let pdf = tr.iter().sum::<f64>() / 3.0;
+            if pdf == 0.0 {
+                return (None, Color::BLACK)
+            }
+            (None, Color::new(tr[0], tr[1], tr[2]) / pdf)
+        }
+    }
+
+    //fraction of light passing through distance of the medium
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let (tx, ty, tz) = self.sigma_t().get_components();
+        let tr = transmittance([tx, ty, tz], distance);
+        Color::new(tr[0], tr[1], tr[2])
+    }
+
+    //new direction after scattering inside the medium
+    pub fn sample_phase(&self, direction: Vec3, u: (f64, f64)) -> Vec3 {
+        self.phase.sample(direction, u)
+    }
+}
+
+fn transmittance(sigma_t: [f64; 3], distance: f64) -> [f64; 3] {
+    sigma_t.map(|s| if s == 0.0 {1.0} else {(-s * distance).exp()})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracer::random_f64;
+
+    #[test]
+    fn from_albedo_coefficients() {
+        let medium = Medium::from_albedo(Color::new(0.5, 0.8, 1.0), Color::new(0.5, 1.0, 2.0));
+        assert_eq!(medium.sigma_t(), Color::new(2.0, 1.0, 0.5));
+        assert_eq!(medium.sigma_s, Color::new(1.0, 0.8, 0.5));
+    }
+
+    #[test]
+    fn distance_sampling_is_unbiased() {
+        //the expected weight of passing through equals the transmittance of each channel
+        let medium = Medium { sigma_a: Color::new(0.2, 1.0, 3.0), sigma_s: Color::BLACK, phase: Phase::Isotropic };
+        let n = 100000;
+        let mut sum = Color::BLACK;
+        for _ in 0..n {
+            if let (None, weight) = medium.sample_distance(0.5, (random_f64(), random_f64())) {
+                sum += weight;
+            }
+        }
+        let estimate = sum / n as f64;
+        for (e, s) in [(estimate.x(), 0.2), (estimate.y(), 1.0), (estimate.z(), 3.0)] {
+            let expected = (-s * 0.5f64).exp();
+            assert!((e - expected).abs() < 0.02, "{} {}", e, expected);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine() {
+        let direction = Vec3::new(1.0, 2.0, -0.5);
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = Phase::HenyeyGreenstein { g };
+            let n = 100000;
+            let mean = (0..n).map(|_| dot(unit_vector(direction), phase.sample(direction, (random_f64(), random_f64())))).sum::<f64>() / n as f64;
+            assert!((mean - g).abs() < 0.01, "{} {}", mean, g);
+        }
+    }
+
+    #[test]
+    fn phase_pdf_integrates_to_one() {
+        let direction = Vec3::new(0.3, -1.0, 0.2);
+        for phase in [Phase::Isotropic, Phase::HenyeyGreenstein { g: -0.5 }, Phase::HenyeyGreenstein { g: 0.8 }] {
+            let n = 400;
+            let mut sum = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                    sum += phase.pdf(direction, Vec3::sample_unit_vector(u));
+                }
+            }
+            let integral = sum * 4.0 * PI / (n * n) as f64;
+            assert!((integral - 1.0).abs() < 0.01, "{:?} {}", phase, integral);
+        }
+    }
+}
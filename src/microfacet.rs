@@ -0,0 +1,211 @@
+use raytracer::{PI, clamp};
+use crate::vec3::{Vec3, unit_vector, cross, dot};
+
+//all directions are in the local shading frame with the normal along +z
+
+//anisotropic Trowbridge-Reitz (GGX) microfacet distribution
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> TrowbridgeReitz {
+        //very small alphas make the lobe numerically unstable
+        TrowbridgeReitz { alpha_x: alpha_x.max(1e-3), alpha_y: alpha_y.max(1e-3) }
+    }
+
+    //perceptually linear roughness to alpha, stretched by anisotropy in [0, 1]
+    pub fn from_roughness(roughness: f64, anisotropic: f64) -> TrowbridgeReitz {
+        let aspect = (1.0 - 0.9 * anisotropic).sqrt();
+        let alpha = roughness * roughness;
+        TrowbridgeReitz::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn d(&self, wm: Vec3) -> f64 {
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let e = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let z2 = w.z() * w.z();
+        if z2 == 0.0 {
+            return f64::INFINITY
+        }
+        let x = w.x() * self.alpha_x;
+        let y = w.y() * self.alpha_y;
+        ((1.0 + (x * x + y * y) / z2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    //density of normals visible from w
+    pub fn d_visible(&self, w: Vec3, wm: Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return 0.0
+        }
+        self.g1(w) / w.z().abs() * self.d(wm) * dot(w, wm).abs()
+    }
+
+    //samples a visible normal for direction w (Heitz 2018)
+    pub fn sample_wm(&self, w: Vec3, u1: f64, u2: f64) -> Vec3 {
+        let mut wh = unit_vector(Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {unit_vector(cross(Vec3::new(0.0, 0.0, 1.0), wh))} else {Vec3::new(1.0, 0.0, 0.0)};
+        let t2 = cross(wh, t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let p2 = r * phi.sin();
+        let s = 0.5 * (1.0 + wh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * wh;
+        unit_vector(Vec3::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)))
+    }
+}
+
+//unpolarized Fresnel reflectance of a dielectric interface with relative index eta
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let mut cos_theta_i = clamp(cos_theta_i, -1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - clamp(cos_theta, 0.0, 1.0)).powi(5)
+}
+
+//mirror wo about n, both pointing away from the surface
+pub fn reflect_local(wo: Vec3, n: Vec3) -> Vec3 {
+    -wo + 2.0 * dot(wo, n) * n
+}
+
+//refracts wi (pointing away from the surface, on the side of n) through an interface with relative index eta
+pub fn refract_local(wi: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = dot(n, wi);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wi / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+//half vector of a rough dielectric interface between wo and wi, wo lying above. None for pairs no
+//microfacet connects, including back facing ones
+fn dielectric_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let etap = if wi.z() > 0.0 {1.0} else {eta};
+    let wm = wi * etap + wo;
+    if wi.z() == 0.0 || wm.length_squared() == 0.0 {
+        return None
+    }
+    let mut wm = unit_vector(wm);
+    if wm.z() < 0.0 {
+        wm = -wm;
+    }
+    //discard back facing microfacets
+    if dot(wm, wi) * wi.z() < 0.0 || dot(wm, wo) < 0.0 {
+        return None
+    }
+    Some(wm)
+}
+
+//bsdf of a rough dielectric interface with relative index eta, reflecting when wi lies above and
+//transmitting when it lies below
+pub fn dielectric_f(distrib: &TrowbridgeReitz, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+    let wm = match dielectric_half_vector(wo, wi, eta) {
+        Some(wm) => wm,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(dot(wo, wm), eta);
+    if wi.z() > 0.0 {
+        distrib.d(wm) * distrib.g(wo, wi) * f / (4.0 * wo.z() * wi.z())
+    } else {
+        let denom = dot(wi, wm) + dot(wo, wm) / eta;
+        distrib.d(wm) * distrib.g(wo, wi) * (1.0 - f) * (dot(wi, wm) * dot(wo, wm)).abs()
+            / (wi.z() * wo.z() * denom * denom).abs()
+    }
+}
+
+//pdf of choosing wi by sampling a visible normal, then reflecting or refracting by the Fresnel term
+pub fn dielectric_pdf(distrib: &TrowbridgeReitz, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+    let wm = match dielectric_half_vector(wo, wi, eta) {
+        Some(wm) => wm,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(dot(wo, wm), eta);
+    if wi.z() > 0.0 {
+        distrib.d_visible(wo, wm) / (4.0 * dot(wo, wm).abs()) * f
+    } else {
+        let denom = dot(wi, wm) + dot(wo, wm) / eta;
+        let dwm_dwi = dot(wi, wm).abs() / (denom * denom);
+        distrib.d_visible(wo, wm) * dwm_dwi * (1.0 - f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresnel_normal_incidence() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fresnel_total_internal_reflection() {
+        assert_eq!(fresnel_dielectric(-0.1, 1.5), 1.0);
+    }
+
+    #[test]
+    fn ggx_normalized() {
+        //the projected area of microfacets integrates to one
+        let distrib = TrowbridgeReitz::new(0.3, 0.6);
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wm = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += distrib.d(wm) * cos_theta;
+            }
+        }
+        let integral = sum * 2.0 * PI / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn refract_straight_through() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let wt = refract_local(n, n, 1.5).expect("should refract");
+        assert!((wt - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use raytracer::{random_f64, with_random_source, RandomSource, PI};
+use crate::ray::Ray;
+use crate::vec3::Color;
+use crate::spectrum::Spectrum;
+use crate::sampler::{Sampler, IndependentSampler};
+use crate::integrator::{Integrator, PathIntegrator};
+use crate::light_sampler::AliasTable;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::scene::Scene;
+use crate::tiles::TILE_SIZE;
+
+//standard deviation of the change a small step makes to each primary sample
+pub const SMALL_STEP_SIGMA: f64 = 0.01;
+
+#[derive(Clone, Copy)]
+struct PrimarySample {
+    value: f64,
+    //value before the current mutation, restored when it is rejected
+    backup: f64,
+    //iteration that last changed the value
+    last_modified: u64,
+    modify_backup: u64,
+}
+
+//point in primary sample space, the random numbers driving a path. samples are mutated lazily when
+//the path asks for them, so a path of any length can be mutated
+pub struct MltSampler {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    large_step_probability: f64,
+}
+
+impl MltSampler {
+    pub fn new(seed: u64, large_step_probability: f64) -> MltSampler {
+        MltSampler {
+            rng: StdRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            large_step_probability,
+        }
+    }
+
+    //starts a mutation, either replacing every sample or moving each a little
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modified == self.iteration {
+                sample.value = sample.backup;
+                sample.last_modified = sample.modify_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    //brings sample i up to date with the mutations made since it was last used
+    fn ensure_ready(&mut self, i: usize) {
+        if i >= self.samples.len() {
+            self.samples.resize(i + 1, PrimarySample { value: 0.0, backup: 0.0, last_modified: 0, modify_backup: 0 });
+        }
+        let sample = &mut self.samples[i];
+
+        //a large step accepted since it was used replaced it too
+        if sample.last_modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.last_modified = self.last_large_step;
+        }
+
+        sample.backup = sample.value;
+        sample.modify_backup = sample.last_modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            //the small steps it missed add up to one with a wider distribution
+            let steps = (self.iteration - sample.last_modified) as f64;
+            let u1: f64 = self.rng.gen();
+            let u2: f64 = self.rng.gen();
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value += normal * SMALL_STEP_SIGMA * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.iteration;
+    }
+}
+
+impl RandomSource for MltSampler {
+    fn next_f64(&mut self) -> f64 {
+        let i = self.index;
+        self.ensure_ready(i);
+        self.index += 1;
+        self.samples[i].value
+    }
+}
+
+//markov chain over paths, with the state it is in
+struct Chain {
+    sampler: MltSampler,
+    u: f64,
+    v: f64,
+    radiance: Color,
+    rng: StdRng,
+}
+
+//primary sample space Metropolis light transport driving the path tracer. paths are found by mutating
+//the random numbers of the current one, and visited in proportion to the light they carry. every call
+//to li makes one mutation and splats both paths onto film, weighted by the acceptance probability.
+//every tile of the image has a chain of its own, which only the thread rendering the tile mutates and
+//in the order of its pixels, so the image is the same with any number of threads
+pub struct MltIntegrator {
+    path: PathIntegrator,
+    camera: Camera,
+    large_step_probability: f64,
+    //mean luminance of the image, found by bootstrapping, that scales the chains' visits back to radiance
+    b: f64,
+    //bootstrap samples chains start from, in proportion to their luminance
+    seeds: Option<AliasTable>,
+    //chains by the column and row of their tile
+    chains: Mutex<HashMap<(u32, u32), Chain>>,
+}
+
+impl MltIntegrator {
+    pub fn new(scene: &Scene, camera: Camera, max_depth: u32, large_step_probability: f64, bootstrap_samples: u32) -> MltIntegrator {
+        let mut integrator = MltIntegrator {
+            path: PathIntegrator { max_depth },
+            camera,
+            large_step_probability,
+            b: 0.0,
+            seeds: None,
+            chains: Mutex::new(HashMap::new()),
+        };
+
+        let weights: Vec<f64> = (0..bootstrap_samples).map(|seed| {
+            let (_, (_, _, radiance)) = with_random_source(MltSampler::new(seed as u64, 1.0), || integrator.radiance(scene));
+            luminance(radiance)
+        }).collect();
+        integrator.b = weights.iter().sum::<f64>() / bootstrap_samples.max(1) as f64;
+        if integrator.b > 0.0 {
+            integrator.seeds = Some(AliasTable::new(&weights));
+        }
+        integrator
+    }
+
+    //film position and light of the path given by the random numbers in use
+    fn radiance(&self, scene: &Scene) -> (f64, f64, Color) {
+        let u = random_f64();
+        let v = random_f64();
+        let r = self.camera.get_ray(u, v, &mut IndependentSampler);
+        //the path tracer doesn't splat
+        let radiance = self.path.li(&r, scene, &mut IndependentSampler, (0, 0), 0, &mut Film::new(1, 1));
+        (u, v, radiance)
+    }
+
+    //new chain starting at a bootstrap sample, so it needs no burn in
+    fn start_chain(&self, scene: &Scene, seeds: &AliasTable) -> Chain {
+        let seed = seeds.sample(random_f64());
+        let (mut sampler, (u, v, radiance)) = with_random_source(MltSampler::new(seed as u64, 1.0), || self.radiance(scene));
+        sampler.large_step_probability = self.large_step_probability;
+        Chain { sampler, u, v, radiance, rng: StdRng::seed_from_u64(random_f64().to_bits()) }
+    }
+}
+
+impl Integrator for MltIntegrator {
+    fn li(&self, _r: &Ray, scene: &Scene, _sampler: &mut dyn Sampler, (i, j): (u32, u32), _index: u32, film: &mut Film) -> Color {
+        let seeds = match self.seeds {
+            Some(ref seeds) => seeds,
+            None => return Color::BLACK,
+        };
+        //the chain of the tile, started by its first sample
+        let tile = (i / TILE_SIZE, j / TILE_SIZE);
+        let chain = self.chains.lock().unwrap().remove(&tile);
+        let mut chain = chain.unwrap_or_else(|| self.start_chain(scene, seeds));
+
+        chain.sampler.start_iteration();
+        let (sampler, (u, v, radiance)) = with_random_source(chain.sampler, || self.radiance(scene));
+        chain.sampler = sampler;
+
+        let current = luminance(chain.radiance);
+        let proposed = luminance(radiance);
+        let acceptance = if current > 0.0 {(proposed / current).min(1.0)} else {1.0};
+        if proposed > 0.0 {
+            film.splat(u, v, radiance * acceptance * self.b / proposed);
+        }
+        if current > 0.0 {
+            film.splat(chain.u, chain.v, chain.radiance * (1.0 - acceptance) * self.b / current);
+        }
+
+        if chain.rng.gen::<f64>() < acceptance {
+            chain.sampler.accept();
+            chain.u = u;
+            chain.v = v;
+            chain.radiance = radiance;
+        } else {
+            chain.sampler.reject();
+        }
+        self.chains.lock().unwrap().insert(tile, chain);
+
+        Color::BLACK
+    }
+
+    fn splats(&self) -> bool {
+        true
+    }
+}
+
+fn luminance(color: Color) -> f64 {
+    Spectrum::Rgb(color).luminance()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use raytracer::Pcg32;
+    use crate::hittable_list::HittableList;
+    use crate::light_sampler::{LightList, LightSelection};
+    use crate::material::Material;
+    use crate::rectangle::RectangleXZ;
+    use crate::sampler::hash64;
+    use crate::sky::Sky;
+    use crate::sphere::Sphere;
+    use crate::tiles::{tiles, TileQueue};
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn rejected_mutation_is_undone() {
+        let mut sampler = MltSampler::new(7, 0.0);
+        sampler.start_iteration();
+        let first: Vec<f64> = (0..4).map(|_| sampler.next_f64()).collect();
+        sampler.accept();
+
+        sampler.start_iteration();
+        let mutated: Vec<f64> = (0..4).map(|_| sampler.next_f64()).collect();
+        assert_ne!(first, mutated);
+        for (a, b) in first.iter().zip(&mutated) {
+            let d = (a - b).abs();
+            assert!(d.min(1.0 - d) < 0.1);
+        }
+        sampler.reject();
+
+        assert_eq!(sampler.samples.iter().map(|s| s.value).collect::<Vec<f64>>(), first);
+    }
+
+    #[test]
+    fn image_is_the_same_with_any_thread_count() {
+        let floor = RectangleXZ::new(-5.0, 5.0, -5.0, 5.0, 0.0, Material::Lambertian { albedo: Color::new(0.7, 0.6, 0.5) });
+        let ball = Sphere::new(Point3::new(0.0, 0.5, 0.0), 0.5, Material::Lambertian { albedo: Color::new(0.5, 0.7, 0.8) });
+        let panel = RectangleXZ::new(-0.5, 0.5, -0.5, 0.5, 2.0, Material::DiffuseLight { emission: Spectrum::Rgb(Color::new(4.0, 4.0, 4.0)) });
+        let dome = Sphere::new(Point3::origin(), 20.0, Material::Lambertian { albedo: Color::BLACK });
+        let scene = Scene {
+            world: HittableList::new(vec![Box::new(floor), Box::new(ball), Box::new(panel.clone()), Box::new(dome)]),
+            lights: LightList::new(vec![Box::new(panel)], LightSelection::Uniform),
+            analytic_lights: Vec::new(),
+            fog: None,
+            sky: Sky::Gradient,
+            spectral: false,
+        };
+        let (width, height, samples) = (80, 48, 2);
+        let camera = Camera::new(Point3::new(0.0, 1.0, 3.0), Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), 60.0, width as f64 / height as f64, 0.0, 3.0);
+
+        //tiles taken from a queue by each thread in turn, with the pixel samples seeded as the renderer does
+        let render = |threads: usize| {
+            let integrator = MltIntegrator::new(&scene, camera, 8, 0.3, 1000);
+            let queue = TileQueue::new(tiles(width, height, TILE_SIZE), threads);
+            let mut film = Film::new(width, height);
+            thread::scope(|s| {
+                let handles: Vec<_> = (0..threads).map(|nth_thread| {
+                    let (integrator, queue, scene) = (&integrator, &queue, &scene);
+                    s.spawn(move || {
+                        let mut film = Film::new(width, height);
+                        while let Some(tile) = queue.pop(nth_thread) {
+                            for (i, j) in tile.pixels() {
+                                for index in 0..samples {
+                                    with_random_source(Pcg32::new(hash64(&[i, j, index, 0]), 1), || {
+                                        let r = Ray::new(Point3::origin(), Vec3::new(0.0, 0.0, -1.0));
+                                        integrator.li(&r, scene, &mut IndependentSampler, (i, j), index, &mut film)
+                                    });
+                                }
+                            }
+                        }
+                        film
+                    })
+                }).collect();
+                for handle in handles {
+                    film.add(&handle.join().unwrap());
+                }
+            });
+            (0..height).flat_map(|j| (0..width).map(move |i| (i, j))).map(|(i, j)| film.pixel(i, j)).collect::<Vec<Color>>()
+        };
+
+        let image = render(1);
+        assert!(image.iter().any(|&color| color != Color::BLACK));
+        assert_eq!(image, render(4));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::vec3::{Vec3, unit_vector, cross, dot};
+
+//orthonormal basis, w is the normal direction
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: Vec3) -> Onb {
+        let w = unit_vector(n);
+        let a = if w.x().abs() > 0.9 {Vec3::new(0.0, 1.0, 0.0)} else {Vec3::new(1.0, 0.0, 0.0)};
+        let v = unit_vector(cross(w, a));
+        let u = cross(w, v);
+        Onb { u, v, w }
+    }
+
+    //local coordinates to world direction
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    //world direction to local coordinates
+    pub fn world_to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(dot(a, self.u), dot(a, self.v), dot(a, self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn onb_orthonormal() {
+        let onb = Onb::build_from_w(Vec3::new(1.0, 2.0, -0.5));
+        assert!((onb.u.length() - 1.0).abs() < 1e-12);
+        assert!((onb.v.length() - 1.0).abs() < 1e-12);
+        assert!(dot(onb.u, onb.v).abs() < 1e-12);
+        assert!(dot(onb.u, onb.w).abs() < 1e-12);
+        assert!(dot(onb.v, onb.w).abs() < 1e-12);
+    }
+
+    #[test]
+    fn onb_round_trip() {
+        let onb = Onb::build_from_w(Vec3::new(0.0, 0.0, -1.0));
+        let a = Vec3::new(0.3, -0.2, 0.9);
+        let result = onb.world_to_local(onb.local(a));
+        assert!((result - a).length() < 1e-12);
+    }
+}
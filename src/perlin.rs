@@ -0,0 +1,92 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use crate::vec3::{Vec3, Point3, dot, unit_vector};
+
+const POINT_COUNT: usize = 256;
+
+//gradient noise, seeded so procedural content is the same on every run
+#[derive(Debug)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| unit_vector(Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0))))
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm_x = permutation();
+        let perm_y = permutation();
+        let perm_z = permutation();
+        Perlin { gradients, perm_x, perm_y, perm_z }
+    }
+
+    //noise value in about [-1, 1], smooth in p
+    pub fn noise(&self, p: Point3) -> f64 {
+        let (x, y, z) = p.get_components();
+        let (u, v, w) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (i, j, k) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+
+        //hermite smoothing of the interpolation weights
+        let (uu, vv, ww) = (u * u * (3.0 - 2.0 * u), v * v * (3.0 - 2.0 * v), w * w * (3.0 - 2.0 * w));
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * dot(self.gradients[index], weight);
+                }
+            }
+        }
+        accum
+    }
+
+    //sum of octaves of noise with halving amplitude
+    pub fn fbm(&self, p: Point3, octaves: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+        accum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perlin_is_seeded() {
+        let p = Point3::new(1.3, -2.7, 0.4);
+        assert_eq!(Perlin::new(7).noise(p), Perlin::new(7).noise(p));
+        assert!(Perlin::new(7).noise(p) != Perlin::new(8).noise(p));
+    }
+
+    #[test]
+    fn perlin_zero_on_lattice() {
+        let perlin = Perlin::new(1);
+        assert_eq!(perlin.noise(Point3::new(3.0, -2.0, 5.0)), 0.0);
+    }
+}
@@ -0,0 +1,197 @@
+use raytracer::{random_f64, random_in_range, INFINITY, PI};
+use crate::ray::Ray;
+use crate::hittable::Hittable;
+use crate::material::{scatter, emitted};
+use crate::vec3::{Vec3, Color, Point3, unit_vector};
+use crate::spectrum::{SampledWavelengths, LAMBDA_MIN, LAMBDA_MAX};
+use crate::light_sampler::AliasTable;
+use crate::integrator::{cosine_direction, SKY_DISTANCE};
+use crate::scene::Scene;
+use crate::sampler::IndependentSampler;
+
+//light that arrived at a surface
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Point3,
+    //unit direction the photon travelled in
+    pub direction: Vec3,
+    //flux carried in rgb
+    pub power: Color,
+}
+
+//photons in a balanced kd-tree, the median of each range is the node splitting it
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    //axis each node splits its range along
+    axes: Vec<u8>,
+}
+
+fn coordinate(p: Point3, axis: u8) -> f64 {
+    match axis {
+        0 => p.x(),
+        1 => p.y(),
+        _ => p.z(),
+    }
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    //calls f for every photon within radius of point
+    pub fn query(&self, point: Point3, radius: f64, f: &mut impl FnMut(&Photon)) {
+        self.query_range(0, self.photons.len(), point, radius * radius, f);
+    }
+
+    fn query_range(&self, lo: usize, hi: usize, point: Point3, radius_squared: f64, f: &mut impl FnMut(&Photon)) {
+        if lo >= hi {
+            return
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        if (photon.position - point).length_squared() <= radius_squared {
+            f(photon);
+        }
+
+        //the side of the split the point is on first, the other one only if the sphere reaches into it
+        let axis = self.axes[mid];
+        let d = coordinate(point, axis) - coordinate(photon.position, axis);
+        let (near, far) = if d <= 0.0 {((lo, mid), (mid + 1, hi))} else {((mid + 1, hi), (lo, mid))};
+        self.query_range(near.0, near.1, point, radius_squared, f);
+        if d * d <= radius_squared {
+            self.query_range(far.0, far.1, point, radius_squared, f);
+        }
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return
+    }
+
+    //split along the longest side of the bounds
+    let mut min = photons[0].position;
+    let mut max = photons[0].position;
+    for photon in photons.iter() {
+        let p = photon.position;
+        min = Point3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+        max = Point3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+    }
+    let extent = max - min;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {0} else if extent.y() >= extent.z() {1} else {2};
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| coordinate(a.position, axis).total_cmp(&coordinate(b.position, axis)));
+    axes[mid] = axis;
+
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+//shoots count photons from the emitters and the point and spot lights, chosen by power.
+//only caustic photons are kept: those reaching a surface that isn't specular after at least one
+//specular one, without scattering in a medium on the way
+pub fn trace_caustic_photons(scene: &Scene, count: usize, max_depth: u32) -> PhotonMap {
+    let mut weights = vec![scene.lights.power()];
+    weights.extend(scene.analytic_lights.iter().map(|light| light.power()));
+    if weights.iter().all(|&w| w <= 0.0) {
+        return PhotonMap::new(Vec::new())
+    }
+    let sources = AliasTable::new(&weights);
+
+    let mut photons = Vec::new();
+    for _ in 0..count {
+        //photons are traced in rgb, each with its own wavelength for dispersion
+        let wavelength = random_in_range(LAMBDA_MIN, LAMBDA_MAX);
+        let lambda = SampledWavelengths::new(wavelength, false);
+
+        let source = sources.sample(random_f64());
+        let (origin, direction, power) = if source == 0 {
+            let (rec, pdf_pos) = match scene.lights.sample_surface(&mut IndependentSampler) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            //the cosine of the emission cancels with that of its density
+            (rec.point, cosine_direction(rec.normal, (random_f64(), random_f64())), emitted(rec.material, &lambda) * PI / pdf_pos)
+        } else {
+            match scene.analytic_lights[source - 1].emit(&lambda) {
+                Some(emission) => emission,
+                None => continue,
+            }
+        };
+
+        let mut r = Ray::new(origin, direction);
+        r.wavelength = wavelength;
+        let mut power = power / (sources.pmf(source) * count as f64);
+
+        for bounce in 0..max_depth {
+            let op_rec = scene.world.hit(&r, 0.001, INFINITY);
+            if let Some(medium) = r.medium.or(scene.fog) {
+                let length = r.direction.length();
+                let distance_max = op_rec.as_ref().map_or(SKY_DISTANCE, |rec| rec.t * length);
+                let (distance, weight) = medium.sampled(&lambda).sample_distance(distance_max, (random_f64(), random_f64()));
+                if distance.is_some() {
+                    break;
+                }
+                power = power * weight;
+            }
+            let rec = match op_rec {
+                Some(rec) => rec,
+                None => break,
+            };
+
+            if !rec.material.is_specular() {
+                //every surface before this one was specular, volumes take their light from paths
+                if bounce > 0 && !rec.material.is_volume() {
+                    photons.push(Photon { position: rec.point, direction: unit_vector(r.direction), power });
+                }
+                break;
+            }
+
+            let mut attenuation = Color::BLACK;
+            let mut r_scattered = Ray::new(Vec3::origin(), Vec3::origin());
+            if !scatter(rec.material, &r, &rec, &mut attenuation, &mut r_scattered, &mut IndependentSampler) {
+                break;
+            }
+            attenuation = lambda.reflectance(attenuation);
+            if rec.material.is_dispersive() && !r.dispersed {
+                attenuation = attenuation * lambda.collapse();
+                r_scattered.dispersed = true;
+            }
+            power = power * attenuation;
+            r = r_scattered;
+        }
+    }
+
+    PhotonMap::new(photons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_matches_brute_force() {
+        let photons: Vec<Photon> = (0..2000).map(|_| Photon {
+            position: Vec3::random_in_range(-1.0, 1.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            power: Color::WHITE,
+        }).collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.photons.len(), 2000);
+
+        for _ in 0..20 {
+            let point = Vec3::random_in_range(-1.0, 1.0);
+            let radius = random_in_range(0.05, 0.5);
+            let mut found = 0;
+            map.query(point, radius, &mut |_| found += 1);
+            let expected = photons.iter().filter(|p| (p.position - point).length() <= radius).count();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use raytracer::PI;
+use crate::vec3::{Vec3, Color, unit_vector, dot};
+use crate::sampler::Sampler;
+use crate::microfacet::{TrowbridgeReitz, fresnel_dielectric, schlick_weight, reflect_local, refract_local, dielectric_f, dielectric_pdf};
+
+//Disney principled BSDF. Directions are in the local shading frame with the
+//normal along +z, wo always lies in the upper hemisphere.
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+    pub ior: f64,
+    pub anisotropic: f64,
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+//cosine weighted direction in the upper hemisphere
+fn cosine_direction((r1, r2): (f64, f64)) -> Vec3 {
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).sqrt())
+}
+
+//Berry distribution of the clearcoat lobe
+fn gtr1(cos_theta_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+//lobes sampled with a probability proportional to these weights
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const GLASS: usize = 2;
+const CLEARCOAT: usize = 3;
+
+impl Principled {
+    //the Disney defaults, a rough white dielectric
+    pub fn new(base_color: Color) -> Principled {
+        Principled {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            anisotropic: 0.0,
+        }
+    }
+
+    fn tint(&self) -> Color {
+        let lum = luminance(self.base_color);
+        if lum > 0.0 {self.base_color / lum} else {Color::WHITE}
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness, self.anisotropic)
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 * (1.0 - self.clearcoat_gloss) + 0.001 * self.clearcoat_gloss
+    }
+
+    //lobe weights in the bsdf sum, only the glass lobe exists on the inside
+    fn lobe_weights(&self, front_face: bool) -> [f64; 4] {
+        if !front_face {
+            return [0.0, 0.0, 1.0, 0.0]
+        }
+        let dielectric = 1.0 - self.metallic;
+        [
+            dielectric * (1.0 - self.transmission),
+            1.0 - dielectric * self.transmission,
+            dielectric * self.transmission,
+            0.25 * self.clearcoat,
+        ]
+    }
+
+    fn lobe_probabilities(&self, front_face: bool) -> [f64; 4] {
+        let weights = self.lobe_weights(front_face);
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    //relative index of refraction seen from the side of wo
+    fn eta(&self, front_face: bool) -> f64 {
+        if front_face {self.ior} else {1.0 / self.ior}
+    }
+
+    fn diffuse(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wi.z() <= 0.0 {
+            return Color::BLACK
+        }
+        let wh = unit_vector(wi + wo);
+        let cos_theta_d = dot(wi, wh);
+        let fl = schlick_weight(wi.z());
+        let fv = schlick_weight(wo.z());
+
+        //Burley diffuse with its retro-reflection term
+        let rr = 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let lambert = (1.0 - 0.5 * fl) * (1.0 - 0.5 * fv);
+        let retro = rr * (fl + fv + fl * fv * (rr - 1.0));
+        let diffuse = self.base_color / PI * (lambert + retro);
+
+        let sheen_color = lerp(Color::WHITE, self.tint(), self.sheen_tint);
+        let sheen = self.sheen * schlick_weight(cos_theta_d) * sheen_color;
+
+        diffuse + sheen
+    }
+
+    fn specular(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wi.z() <= 0.0 {
+            return Color::BLACK
+        }
+        let wh = unit_vector(wi + wo);
+        let distrib = self.distribution();
+
+        let specular_color = self.specular * 0.08 * lerp(Color::WHITE, self.tint(), self.specular_tint);
+        let f0 = lerp(specular_color, self.base_color, self.metallic);
+        let f = lerp(f0, Color::WHITE, schlick_weight(dot(wi, wh)));
+
+        distrib.d(wh) * distrib.g(wo, wi) / (4.0 * wo.z() * wi.z()) * f
+    }
+
+    fn clearcoat(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wi.z() <= 0.0 {
+            return 0.0
+        }
+        let wh = unit_vector(wi + wo);
+        let d = gtr1(wh.z(), self.clearcoat_alpha());
+        let f = 0.04 + 0.96 * schlick_weight(dot(wi, wh));
+        let g = TrowbridgeReitz::new(0.25, 0.25).g(wo, wi);
+        d * f * g / (4.0 * wo.z() * wi.z())
+    }
+
+    //rough dielectric, reflecting and transmitting
+    fn glass(&self, wo: Vec3, wi: Vec3, front_face: bool) -> Color {
+        let value = dielectric_f(&self.distribution(), wo, wi, self.eta(front_face));
+        if wi.z() > 0.0 {
+            Color::WHITE * value
+        } else {
+            self.base_color * value
+        }
+    }
+
+    fn glass_pdf(&self, wo: Vec3, wi: Vec3, front_face: bool) -> f64 {
+        dielectric_pdf(&self.distribution(), wo, wi, self.eta(front_face))
+    }
+
+    //bsdf times the cosine of wi
+    pub fn eval(&self, wo: Vec3, wi: Vec3, front_face: bool) -> Color {
+        let w = self.lobe_weights(front_face);
+        let mut f = w[GLASS] * self.glass(wo, wi, front_face);
+        if front_face {
+            f += w[DIFFUSE] * self.diffuse(wo, wi);
+            f += w[SPECULAR] * self.specular(wo, wi);
+            f += Color::WHITE * (w[CLEARCOAT] * self.clearcoat(wo, wi));
+        }
+        f * wi.z().abs()
+    }
+
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, front_face: bool) -> f64 {
+        let p = self.lobe_probabilities(front_face);
+        let mut pdf = p[GLASS] * self.glass_pdf(wo, wi, front_face);
+
+        if front_face && wi.z() > 0.0 {
+            let wh = unit_vector(wi + wo);
+            pdf += p[DIFFUSE] * wi.z() / PI;
+            pdf += p[SPECULAR] * self.distribution().d_visible(wo, wh) / (4.0 * dot(wo, wh));
+            pdf += p[CLEARCOAT] * gtr1(wh.z(), self.clearcoat_alpha()) * wh.z() / (4.0 * dot(wo, wh));
+        }
+        pdf
+    }
+
+    //samples an incoming direction, returns it with the bsdf times cosine over the pdf
+    pub fn sample(&self, wo: Vec3, front_face: bool, sampler: &mut dyn Sampler) -> Option<(Vec3, Color)> {
+        let p = self.lobe_probabilities(front_face);
+        let u = sampler.get_1d();
+        let (u1, u2) = sampler.get_2d();
+
+        let wi = if u < p[DIFFUSE] {
+            cosine_direction((u1, u2))
+        } else if u < p[DIFFUSE] + p[SPECULAR] {
+            let wm = self.distribution().sample_wm(wo, u1, u2);
+            reflect_local(wo, wm)
+        } else if u < p[DIFFUSE] + p[SPECULAR] + p[GLASS] {
+            let wm = self.distribution().sample_wm(wo, u1, u2);
+            let eta = self.eta(front_face);
+            let f = fresnel_dielectric(dot(wo, wm), eta);
+            if sampler.get_1d() < f {
+                reflect_local(wo, wm)
+            } else {
+                refract_local(wo, wm, eta)?
+            }
+        } else {
+            let a2 = self.clearcoat_alpha() * self.clearcoat_alpha();
+            let cos_theta = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u2;
+            let wh = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            reflect_local(wo, wh)
+        };
+
+        let pdf = self.pdf(wo, wi, front_face);
+        if pdf <= 0.0 {
+            return None
+        }
+        Some((wi, self.eval(wo, wi, front_face) / pdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    fn wo() -> Vec3 {
+        unit_vector(Vec3::new(0.3, -0.2, 0.8))
+    }
+
+    //integrates the pdf over the sphere of directions
+    fn pdf_integral(p: &Principled, front_face: bool) -> f64 {
+        let n = 300;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = 2.0 * (i as f64 + 0.5) / n as f64 - 1.0;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += p.pdf(wo(), wi, front_face);
+            }
+        }
+        sum * 4.0 * PI / (n * n) as f64
+    }
+
+    //fraction of samples that produce a direction
+    fn sample_success(p: &Principled, front_face: bool) -> f64 {
+        let n = 20000;
+        let success = (0..n).filter(|_| p.sample(wo(), front_face, &mut IndependentSampler).is_some()).count();
+        success as f64 / n as f64
+    }
+
+    #[test]
+    fn pdf_matches_sampling_opaque() {
+        let p = Principled { clearcoat: 1.0, clearcoat_gloss: 0.5, sheen: 0.5, ..Principled::new(Color::new(0.8, 0.2, 0.1)) };
+        let integral = pdf_integral(&p, true);
+        assert!((integral - sample_success(&p, true)).abs() < 0.02, "{}", integral);
+    }
+
+    #[test]
+    fn pdf_matches_sampling_glass() {
+        let p = Principled { transmission: 1.0, roughness: 0.4, ..Principled::new(Color::WHITE) };
+        for front_face in [true, false] {
+            let integral = pdf_integral(&p, front_face);
+            assert!((integral - sample_success(&p, front_face)).abs() < 0.02, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn white_furnace_diffuse() {
+        //a white rough dielectric should not reflect more than it receives
+        let p = Principled { specular: 0.0, ..Principled::new(Color::WHITE) };
+        let n = 20000;
+        let mut sum = Color::BLACK;
+        for _ in 0..n {
+            if let Some((_, weight)) = p.sample(wo(), true, &mut IndependentSampler) {
+                sum += weight;
+            }
+        }
+        let albedo = sum / n as f64;
+        assert!(albedo.x() < 1.1 && albedo.x() > 0.8, "{:?}", albedo);
+    }
+
+    #[test]
+    fn glass_mostly_transmits() {
+        let p = Principled { transmission: 1.0, roughness: 0.1, ..Principled::new(Color::WHITE) };
+        let normal_incidence = Vec3::new(0.0, 0.0, 1.0);
+        let n = 1000;
+        let transmitted = (0..n)
+            .filter_map(|_| p.sample(normal_incidence, true, &mut IndependentSampler))
+            .filter(|(wi, _)| wi.z() < 0.0)
+            .count();
+        assert!(transmitted > 900);
+    }
+}
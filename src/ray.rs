@@ -1,14 +1,28 @@
 use crate::vec3::*;
+use crate::spectrum::LAMBDA_D;
+use crate::medium::Medium;
 
 #[derive(Debug, Copy, Clone)]
 pub struct  Ray {
     pub origin: Point3,
     pub direction: Vec3,
+
+    //wavelength in nm sampled by the camera path this ray belongs to
+    pub wavelength: f64,
+    //set once the path went through a dispersive interface and only carries its own wavelength
+    pub dispersed: bool,
+    //medium the ray travels through, None for the medium filling the scene
+    pub medium: Option<Medium>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray { origin, direction, wavelength: LAMBDA_D, dispersed: false, medium: None }
+    }
+
+    //continues the path of this ray from origin in a new direction
+    pub fn bounce(&self, origin: Point3, direction: Vec3) -> Ray {
+        Ray { origin, direction, ..*self }
     }
 
     pub fn at(self, t: f64) -> Point3 {
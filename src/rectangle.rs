@@ -1,7 +1,9 @@
-use crate::material::Material;
-use crate::vec3::{Vec3};
+use raytracer::{INFINITY, PI};
+use crate::material::{Material, emitted_luminance};
+use crate::vec3::{Vec3, Point3};
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+#[derive(Clone)]
 pub struct RectangleXY {
     x0: f64,
     x1: f64,
@@ -11,6 +13,8 @@ pub struct RectangleXY {
     material: Material,
 }
 
+#[allow(dead_code)]
+#[derive(Clone)]
 pub struct RectangleYZ {
     y0: f64,
     y1: f64,
@@ -20,6 +24,7 @@ pub struct RectangleYZ {
     material: Material,
 }
 
+#[derive(Clone)]
 pub struct RectangleXZ {
     x0: f64,
     x1: f64,
@@ -34,13 +39,22 @@ impl RectangleXY {
         RectangleXY { x0, x1, y0, y1, k, material }
     }
 
+    //point of the rectangle at fractions u of its sides
+    fn point(&self, (u1, u2): (f64, f64)) -> Point3 {
+        Point3::new(self.x0 + u1 * (self.x1 - self.x0), self.y0 + u2 * (self.y1 - self.y0), self.k)
+    }
 }
 
+#[allow(dead_code)]
 impl RectangleYZ {
     pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k:f64, material: Material) -> RectangleYZ{
         RectangleYZ { y0, y1, z0, z1, k, material }
     }
 
+    //point of the rectangle at fractions u of its sides
+    fn point(&self, (u1, u2): (f64, f64)) -> Point3 {
+        Point3::new(self.k, self.y0 + u1 * (self.y1 - self.y0), self.z0 + u2 * (self.z1 - self.z0))
+    }
 }
 
 impl RectangleXZ {
@@ -48,10 +62,14 @@ impl RectangleXZ {
         RectangleXZ { x0, x1, z0, z1, k, material }
     }
 
+    //point of the rectangle at fractions u of its sides
+    fn point(&self, (u1, u2): (f64, f64)) -> Point3 {
+        Point3::new(self.x0 + u1 * (self.x1 - self.x0), self.k, self.z0 + u2 * (self.z1 - self.z0))
+    }
 }
 
 impl Hittable for RectangleXY {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         //find t value for when ray hits plane of rectangle
         let a_z = r.origin.z();
         let b_z = r.direction.z();
@@ -74,17 +92,49 @@ impl Hittable for RectangleXY {
             rec.point = Vec3::new(x, y, self.k);
             let outward_normal = Vec3::new(0.0, 0.0, 1.0);
             rec.set_face_normal(r, outward_normal);
-            rec.material = self.material;
+            rec.material = &self.material;
 
-            return Some(rec);
+            Some(rec)
         } else {
-            return None
+            None
         }
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, INFINITY) {
+            Some(rec) => area_pdf((self.x1 - self.x0) * (self.y1 - self.y0), rec.t, direction, direction.z()),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+        self.point(u) - origin
+    }
+
+    //lights emit from both sides
+    fn power(&self) -> f64 {
+        2.0 * PI * (self.x1 - self.x0) * (self.y1 - self.y0) * emitted_luminance(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64), side: f64) -> Option<HitRecord<'_>> {
+        Some(HitRecord::on_side(self.point(u), Vec3::new(0.0, 0.0, 1.0), &self.material, side))
+    }
+
+    fn surface_pdf(&self) -> f64 {
+        1.0 / (2.0 * (self.x1 - self.x0) * (self.y1 - self.y0))
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        Some((Point3::new(self.x0, self.y0, self.k - PAD), Point3::new(self.x1, self.y1, self.k + PAD)))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
 }
 
 impl Hittable for RectangleYZ {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         //find t value for when ray hits plane of rectangle
         let a_x = r.origin.x();
         let b_x = r.direction.x();
@@ -107,17 +157,49 @@ impl Hittable for RectangleYZ {
             rec.point = Vec3::new(self.k, y, z);
             let outward_normal = Vec3::new(1.0, 0.0, 0.0);
             rec.set_face_normal(r, outward_normal);
-            rec.material = self.material;
+            rec.material = &self.material;
 
-            return Some(rec);
+            Some(rec)
         } else {
-            return None
+            None
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, INFINITY) {
+            Some(rec) => area_pdf((self.y1 - self.y0) * (self.z1 - self.z0), rec.t, direction, direction.x()),
+            None => 0.0,
         }
     }
+
+    fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+        self.point(u) - origin
+    }
+
+    //lights emit from both sides
+    fn power(&self) -> f64 {
+        2.0 * PI * (self.y1 - self.y0) * (self.z1 - self.z0) * emitted_luminance(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64), side: f64) -> Option<HitRecord<'_>> {
+        Some(HitRecord::on_side(self.point(u), Vec3::new(1.0, 0.0, 0.0), &self.material, side))
+    }
+
+    fn surface_pdf(&self) -> f64 {
+        1.0 / (2.0 * (self.y1 - self.y0) * (self.z1 - self.z0))
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        Some((Point3::new(self.k - PAD, self.y0, self.z0), Point3::new(self.k + PAD, self.y1, self.z1)))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
 }
 
 impl Hittable for RectangleXZ {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         //find t value for when ray hits plane of rectangle
         let a_y = r.origin.y();
         let b_y = r.direction.y();
@@ -140,18 +222,89 @@ impl Hittable for RectangleXZ {
             rec.point = Vec3::new(x, self.k, z);
             let outward_normal = Vec3::new(0.0, 1.0, 0.0);
             rec.set_face_normal(r, outward_normal);
-            rec.material = self.material;
+            rec.material = &self.material;
 
-            return Some(rec);
+            Some(rec)
         } else {
-            return None
+            None
         }
     }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, INFINITY) {
+            Some(rec) => area_pdf((self.x1 - self.x0) * (self.z1 - self.z0), rec.t, direction, direction.y()),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3, u: (f64, f64)) -> Vec3 {
+        self.point(u) - origin
+    }
+
+    //lights emit from both sides
+    fn power(&self) -> f64 {
+        2.0 * PI * (self.x1 - self.x0) * (self.z1 - self.z0) * emitted_luminance(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64), side: f64) -> Option<HitRecord<'_>> {
+        Some(HitRecord::on_side(self.point(u), Vec3::new(0.0, 1.0, 0.0), &self.material, side))
+    }
+
+    fn surface_pdf(&self) -> f64 {
+        1.0 / (2.0 * (self.x1 - self.x0) * (self.z1 - self.z0))
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        Some((Point3::new(self.x0, self.k - PAD, self.z0), Point3::new(self.x1, self.k + PAD, self.z1)))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
+    }
+}
+
+//half thickness of the bounding box of a rectangle
+const PAD: f64 = 0.0001;
+
+//solid angle density of a uniformly sampled point on a rectangle hit at t,
+//normal_component is the direction's component along the rectangle's normal
+fn area_pdf(area: f64, t: f64, direction: Vec3, normal_component: f64) -> f64 {
+    let distance_squared = t * t * direction.length_squared();
+    let cosine = (normal_component / direction.length()).abs();
+    distance_squared / (cosine * area)
 }
 
-/*
 #[cfg(test)]
 mod tests {
-    //use super::*;
+    use super::*;
+    use raytracer::random_f64;
+    use crate::vec3::Color;
+    use crate::spectrum::Spectrum;
+
+    #[test]
+    fn rectangle_sampling() {
+        let rect = RectangleXZ::new(-0.01, 0.01, -0.01, 0.01, 2.0, Material::Lambertian { albedo: Color::WHITE });
+        let origin = Vec3::origin();
+
+        //a small rectangle straight above covers a solid angle of about area / distance^2
+        let direction = rect.random(origin, (random_f64(), random_f64()));
+        assert!(rect.hit(&Ray::new(origin, direction), 0.001, INFINITY).is_some());
+        let pdf = rect.pdf_value(origin, direction);
+        assert!((pdf - 4.0 / 0.0004).abs() / pdf < 1e-3);
+        assert_eq!(rect.pdf_value(origin, Vec3::new(1.0, 0.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn rectangle_yz_sampling() {
+        let rect = RectangleYZ::new(-1.0, 1.0, -1.0, 1.0, -3.0, Material::DiffuseLight { emission: Spectrum::Rgb(Color::WHITE) });
+        let origin = Vec3::origin();
+
+        let direction = rect.random(origin, (random_f64(), random_f64()));
+        let rec = rect.hit(&Ray::new(origin, direction), 0.001, INFINITY).expect("sampled point should be hit");
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!(rect.pdf_value(origin, direction) > 0.0);
+        assert_eq!(rect.surface_pdf(), 1.0 / 8.0);
+        let (min, max) = rect.bounding_box().unwrap();
+        assert!(min.x() < -3.0 && max.x() > -3.0);
+    }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,281 @@
+use std::sync::OnceLock;
+use crate::vec3::{Vec3, Color, dot, cross};
+use crate::spectrum::{LAMBDA_MIN, LAMBDA_MAX, LAMBDA_SAMPLES, lambda_sample, cie_xyz, d65, xyz_to_output_rgb};
+
+//resolution of the precomputed coefficient table along each axis
+const TABLE_RES: usize = 16;
+
+//smooth spectrum given by a sigmoid of a quadratic polynomial in wavelength,
+//upsampled from rgb with the method of Jakob and Hanika
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct RgbSpectrum {
+    c: [f64; 3],
+    scale: f64,
+}
+
+impl RgbSpectrum {
+    //spectrum reproducing an rgb reflectance under D65, values above 1 are scaled down before fitting
+    pub fn new(rgb: Color) -> RgbSpectrum {
+        let rgb = clamp_negative(rgb);
+        let m = rgb.x().max(rgb.y()).max(rgb.z());
+        if m <= 1.0 {
+            RgbSpectrum { c: table().lookup(rgb), scale: 1.0 }
+        } else {
+            RgbSpectrum { c: table().lookup(rgb / (2.0 * m)), scale: 2.0 * m }
+        }
+    }
+
+    //spectrum of a light with the given rgb color, meant to be multiplied by D65
+    pub fn illuminant(rgb: Color) -> RgbSpectrum {
+        let rgb = clamp_negative(rgb);
+        let m = rgb.x().max(rgb.y()).max(rgb.z());
+        if m <= 0.0 {
+            return RgbSpectrum { c: [0.0, 0.0, 0.0], scale: 0.0 }
+        }
+        RgbSpectrum { c: table().lookup(rgb / (2.0 * m)), scale: 2.0 * m }
+    }
+
+    pub fn at(&self, lambda: f64) -> f64 {
+        self.scale * sigmoid(polynomial(&self.c, normalized(lambda)))
+    }
+}
+
+fn clamp_negative(rgb: Color) -> Color {
+    Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+}
+
+//maps wavelengths to [0, 1] to keep the fit well conditioned
+fn normalized(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn polynomial(c: &[f64; 3], x: f64) -> f64 {
+    (c[0] * x + c[1]) * x + c[2]
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 {1.0} else {0.0}
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+fn sigmoid_derivative(x: f64) -> f64 {
+    0.5 / (1.0 + x * x).powf(1.5)
+}
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+//coefficients for rgb triplets, indexed by the largest channel, its value and
+//the ratios of the two other channels to it
+struct CoefficientTable {
+    scale: [f64; TABLE_RES],
+    data: Vec<[f64; 3]>,
+}
+
+fn table() -> &'static CoefficientTable {
+    static TABLE: OnceLock<CoefficientTable> = OnceLock::new();
+    TABLE.get_or_init(CoefficientTable::build)
+}
+
+impl CoefficientTable {
+    fn index(l: usize, zi: usize, yi: usize, xi: usize) -> usize {
+        ((l * TABLE_RES + zi) * TABLE_RES + yi) * TABLE_RES + xi
+    }
+
+    fn build() -> CoefficientTable {
+        let scale = std::array::from_fn(|i| smoothstep(smoothstep(i as f64 / (TABLE_RES - 1) as f64)));
+        let fit = Fit::new();
+        let mut data = vec![[0.0; 3]; 3 * TABLE_RES * TABLE_RES * TABLE_RES];
+
+        //neighbouring entries have similar solutions, so each fit starts from the previous one,
+        //going up and down from a medium brightness where the optimization is well behaved
+        let start = TABLE_RES / 5;
+        for l in 0..3 {
+            for yi in 0..TABLE_RES {
+                let y = yi as f64 / (TABLE_RES - 1) as f64;
+                for xi in 0..TABLE_RES {
+                    let x = xi as f64 / (TABLE_RES - 1) as f64;
+                    let target = |z: f64| {
+                        let mut rgb = [0.0; 3];
+                        rgb[l] = z;
+                        rgb[(l + 1) % 3] = x * z;
+                        rgb[(l + 2) % 3] = y * z;
+                        Color::new(rgb[0], rgb[1], rgb[2])
+                    };
+
+                    let mut c = [0.0; 3];
+                    for zi in start..TABLE_RES {
+                        fit.solve(target(scale[zi]), &mut c);
+                        data[Self::index(l, zi, yi, xi)] = c;
+                    }
+                    c = data[Self::index(l, start, yi, xi)];
+                    for zi in (0..start).rev() {
+                        fit.solve(target(scale[zi]), &mut c);
+                        data[Self::index(l, zi, yi, xi)] = c;
+                    }
+                }
+            }
+        }
+
+        CoefficientTable { scale, data }
+    }
+
+    //trilinearly interpolated coefficients for an rgb triplet in [0, 1]
+    fn lookup(&self, rgb: Color) -> [f64; 3] {
+        let rgb = [rgb.x().min(1.0), rgb.y().min(1.0), rgb.z().min(1.0)];
+
+        if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+            //constant spectrum, invert the sigmoid directly
+            let v = rgb[0];
+            let c = if v <= 0.0 {
+                f64::NEG_INFINITY
+            } else if v >= 1.0 {
+                f64::INFINITY
+            } else {
+                (v - 0.5) / (v * (1.0 - v)).sqrt()
+            };
+            return [0.0, 0.0, c]
+        }
+
+        let l = if rgb[0] >= rgb[1] && rgb[0] >= rgb[2] {0} else if rgb[1] >= rgb[2] {1} else {2};
+        let z = rgb[l];
+        let res = (TABLE_RES - 1) as f64;
+        let x = rgb[(l + 1) % 3] / z * res;
+        let y = rgb[(l + 2) % 3] / z * res;
+
+        let xi = (x as usize).min(TABLE_RES - 2);
+        let yi = (y as usize).min(TABLE_RES - 2);
+        let zi = self.scale.iter().rposition(|&s| s <= z).unwrap_or(0).min(TABLE_RES - 2);
+
+        let dx = x - xi as f64;
+        let dy = y - yi as f64;
+        let dz = (z - self.scale[zi]) / (self.scale[zi + 1] - self.scale[zi]);
+
+        let mut c = [0.0; 3];
+        for (k, ck) in c.iter_mut().enumerate() {
+            let at = |zo: usize, yo: usize, xo: usize| self.data[Self::index(l, zi + zo, yi + yo, xi + xo)][k];
+            let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+            *ck = lerp(
+                lerp(lerp(at(0, 0, 0), at(0, 0, 1), dx), lerp(at(0, 1, 0), at(0, 1, 1), dx), dy),
+                lerp(lerp(at(1, 0, 0), at(1, 0, 1), dx), lerp(at(1, 1, 0), at(1, 1, 1), dx), dy),
+                dz,
+            );
+        }
+        c
+    }
+}
+
+//Gauss-Newton fit of sigmoid coefficients to an rgb reflectance
+struct Fit {
+    //normalized wavelength and the rgb response of each integration step under D65
+    samples: Vec<(f64, Vec3)>,
+}
+
+impl Fit {
+    fn new() -> Fit {
+        let samples = (0..LAMBDA_SAMPLES).map(|i| {
+            let lambda = lambda_sample(i);
+            (normalized(lambda), xyz_to_output_rgb(cie_xyz(lambda) * d65(lambda)))
+        }).collect::<Vec<_>>();
+
+        //normalize so a constant spectrum of 1 under D65 integrates to white, as it does on the film
+        let mut total = Vec3::origin();
+        for &(_, w) in &samples {
+            total += w;
+        }
+        let samples = samples.into_iter().map(|(x, w)| (x, w / total)).collect();
+
+        Fit { samples }
+    }
+
+    //rgb of the spectrum and its jacobian with respect to the coefficients
+    fn evaluate(&self, c: &[f64; 3]) -> (Color, [Vec3; 3]) {
+        let mut rgb = Vec3::origin();
+        let mut jacobian = [Vec3::origin(); 3];
+        for &(x, w) in &self.samples {
+            let p = polynomial(c, x);
+            rgb += sigmoid(p) * w;
+            let d = sigmoid_derivative(p);
+            jacobian[0] += d * x * x * w;
+            jacobian[1] += d * x * w;
+            jacobian[2] += d * w;
+        }
+        (rgb, jacobian)
+    }
+
+    fn solve(&self, target: Color, c: &mut [f64; 3]) {
+        for _ in 0..32 {
+            let (rgb, jacobian) = self.evaluate(c);
+            let residual = rgb - target;
+            if residual.length() < 1e-6 {
+                break
+            }
+            let delta = match solve3(jacobian, residual) {
+                Some(delta) => delta,
+                None => break,
+            };
+            for k in 0..3 {
+                c[k] -= delta[k];
+            }
+            //keep the coefficients from running off for unreachable targets
+            let max = c.iter().fold(0.0_f64, |m, v| m.max(v.abs()));
+            if max > 200.0 {
+                for ck in c.iter_mut() {
+                    *ck *= 200.0 / max;
+                }
+            }
+        }
+    }
+}
+
+//solves the 3x3 system with the given columns using Cramer's rule
+fn solve3(columns: [Vec3; 3], rhs: Vec3) -> Option<[f64; 3]> {
+    let det = |a: Vec3, b: Vec3, c: Vec3| dot(a, cross(b, c));
+    let d = det(columns[0], columns[1], columns[2]);
+    if d.abs() < 1e-15 {
+        return None
+    }
+    Some([
+        det(rhs, columns[1], columns[2]) / d,
+        det(columns[0], rhs, columns[2]) / d,
+        det(columns[0], columns[1], rhs) / d,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::spectrum_to_xyz;
+
+    fn round_trip(rgb: Color) -> Color {
+        let s = RgbSpectrum::new(rgb);
+        let xyz = spectrum_to_xyz(|l| s.at(l) * d65(l));
+        xyz_to_output_rgb(xyz)
+    }
+
+    #[test]
+    fn gray_is_constant() {
+        let s = RgbSpectrum::new(Color::new(0.3, 0.3, 0.3));
+        assert!((s.at(400.0) - 0.3).abs() < 1e-9);
+        assert!((s.at(700.0) - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn round_trip_colors() {
+        for rgb in [Color::new(0.1, 0.2, 0.8), Color::new(0.7, 0.8, 0.3), Color::new(0.28, 0.95, 0.55)] {
+            let result = round_trip(rgb);
+            assert!((result - rgb).length() < 0.02, "{:?} -> {:?}", rgb, result);
+        }
+    }
+
+    #[test]
+    fn unbounded_scale() {
+        let rgb = Color::new(4.0, 2.0, 1.0);
+        let result = round_trip(rgb);
+        assert!((result - rgb).length() < 0.1, "{:?}", result);
+    }
+}
@@ -0,0 +1,503 @@
+use std::sync::OnceLock;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use raytracer::random_f64;
+
+//source of the random numbers an integrator draws for one pixel sample. the numbers are
+//dimensions of the sample, drawn in the same order for every sample of the image, so samplers
+//can spread each dimension evenly over the samples of a pixel
+pub trait Sampler {
+    //moves to sample index of pixel, the next number drawn is the first dimension
+    fn start_pixel_sample(&mut self, _pixel: (u32, u32), _index: u32) {}
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+//sampler chosen in the configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+//sampler for pixels taking up to samples_per_pixel samples, scrambled by seed
+pub fn create(kind: SamplerKind, samples_per_pixel: u32, seed: u32) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(IndependentSampler),
+        SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+    }
+}
+
+//independent uniform random numbers, from the random source installed for the pixel sample
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&mut self) -> f64 {
+        random_f64()
+    }
+}
+
+//mixes the bits of values into a well distributed hash, used to decorrelate pixels and dimensions
+pub fn hash(values: &[u32]) -> u32 {
+    (hash64(values) >> 32) as u32
+}
+
+pub fn hash64(values: &[u32]) -> u64 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for &value in values {
+        h ^= value as u64;
+        //splitmix64 finalizer
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+    }
+    h
+}
+
+//number in [0, 1) from the top bits of x
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+//element i of a random permutation of 0..n chosen by seed, without storing it (Kensler 2013)
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+//pixel, sample and dimension the dimension-indexed samplers are at
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleState {
+    seed: u32,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, pixel: (u32, u32), index: u32) {
+        *self = SampleState { seed: self.seed, pixel, index, dimension: 0 };
+    }
+
+    //dimension to draw next, moving past it
+    fn next(&mut self) -> u32 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+
+    fn hash(&self, dimension: u32, salt: u32) -> u32 {
+        hash(&[self.pixel.0, self.pixel.1, dimension, salt, self.seed])
+    }
+}
+
+//jittered samples, every dimension of a pixel's samples has one in each of samples_per_pixel strata,
+//and 2d samples one in each cell of a grid, visited in an order shuffled per pixel and dimension
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u32) -> StratifiedSampler {
+        StratifiedSampler { samples_per_pixel: samples_per_pixel.max(1), state: SampleState { seed, ..SampleState::default() } }
+    }
+
+    fn jitter(&self, dimension: u32) -> f64 {
+        to_unit(hash(&[self.state.pixel.0, self.state.pixel.1, self.state.index, dimension, self.state.seed]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next();
+        let n = self.samples_per_pixel;
+        let stratum = permutation_element(self.state.index % n, n, self.state.hash(dimension, 0));
+        (stratum as f64 + self.jitter(dimension)) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next();
+        self.state.next();
+        //the smallest square grid with a cell for every sample
+        let side = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let cells = side * side;
+        let cell = permutation_element(self.state.index % cells, cells, self.state.hash(dimension, 0));
+        let x = (cell % side) as f64 + self.jitter(dimension);
+        let y = (cell / side) as f64 + self.jitter(dimension + 1);
+        (x / side as f64, y / side as f64)
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+//digits of index in base mirrored around the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut result = 0.0;
+    let mut scale = inverse_base;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    result
+}
+
+//Halton sequence with a prime base per dimension, shifted by a random offset per pixel and
+//dimension (Cranley-Patterson rotation) so pixels don't repeat the same points
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u32) -> HaltonSampler {
+        HaltonSampler { state: SampleState { seed, ..SampleState::default() } }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next();
+        //reusing the bases would repeat the points of the first dimensions with another shift, so
+        //dimensions past the table are independent hashed values
+        let base = match PRIMES.get(dimension as usize) {
+            Some(&base) => base,
+            None => return to_unit(hash(&[self.state.pixel.0, self.state.pixel.1, self.state.index, dimension, self.state.seed])),
+        };
+        let value = radical_inverse(base, self.state.index) + to_unit(self.state.hash(dimension, 1));
+        value - value.floor()
+    }
+}
+
+//generator matrices of the first four dimensions of the Sobol sequence, as direction numbers.
+//the first is the van der Corput sequence, the others come from the primitive polynomials and
+//initial numbers of Joe and Kuo
+fn sobol_matrices() -> &'static [[u32; 32]; 4] {
+    static MATRICES: OnceLock<[[u32; 32]; 4]> = OnceLock::new();
+    MATRICES.get_or_init(|| {
+        //degree, coefficients and initial direction numbers
+        let parameters: [(usize, u32, [u32; 3]); 3] = [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+        let mut matrices = [[0; 32]; 4];
+        for (k, v) in matrices[0].iter_mut().enumerate() {
+            *v = 1 << (31 - k);
+        }
+        for (d, &(s, a, m)) in parameters.iter().enumerate() {
+            let v = &mut matrices[d + 1];
+            for k in 0..32 {
+                v[k] = if k < s {
+                    m[k] << (31 - k)
+                } else {
+                    let mut x = v[k - s] ^ (v[k - s] >> s);
+                    for l in 1..s {
+                        if (a >> (s - 1 - l)) & 1 == 1 {
+                            x ^= v[k - l];
+                        }
+                    }
+                    x
+                };
+            }
+        }
+        matrices
+    })
+}
+
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let matrix = &sobol_matrices()[dimension];
+    let mut x = 0;
+    let mut i = index;
+    let mut bit = 0;
+    while i != 0 {
+        if i & 1 == 1 {
+            x ^= matrix[bit];
+        }
+        i >>= 1;
+        bit += 1;
+    }
+    x
+}
+
+//random permutation of the higher bits depending only on the lower ones (Laine and Karras)
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+//Owen scrambling, a random permutation of each digit depending on the digits above it
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+//Owen-scrambled Sobol points (Burley 2020). dimensions are taken from the first four of the
+//sequence in groups of four, each group with its own shuffle of the samples and scrambling
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32) -> SobolSampler {
+        SobolSampler { state: SampleState { seed, ..SampleState::default() } }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next();
+        let group = dimension / 4;
+        let index = nested_uniform_scramble(self.state.index, self.state.hash(group, 2));
+        let value = nested_uniform_scramble(sobol(index, (dimension % 4) as usize), self.state.hash(dimension, 3));
+        to_unit(value)
+    }
+}
+
+//side of the tiling blue noise mask
+pub const BLUE_NOISE_SIZE: usize = 64;
+
+//ranks of a blue noise mask in [0, 1), made by void and cluster (Ulichney 1993): pixels are
+//ordered so that every prefix is spread as evenly as possible
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE;
+        let n = size * size;
+        //gaussian energy every pixel adds around it, wrapping around the edges
+        let sigma2 = 2.0 * 1.5 * 1.5;
+        let mut kernel = vec![0.0; n];
+        for y in 0..size {
+            for x in 0..size {
+                let dx = x.min(size - x) as f64;
+                let dy = y.min(size - y) as f64;
+                kernel[y * size + x] = (-(dx * dx + dy * dy) / sigma2).exp();
+            }
+        }
+        let update = |energy: &mut [f64], p: usize, sign: f64| {
+            let (px, py) = (p % size, p / size);
+            for y in 0..size {
+                for x in 0..size {
+                    let k = ((y + size - py) % size) * size + (x + size - px) % size;
+                    energy[y * size + x] += sign * kernel[k];
+                }
+            }
+        };
+        //densest pixel that is set, or emptiest that isn't
+        let find = |energy: &[f64], pattern: &[bool], set: bool| -> usize {
+            let candidates = (0..n).filter(|&p| pattern[p] == set);
+            if set {
+                candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+            } else {
+                candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+            }
+        };
+
+        //initial pattern, relaxed by moving its tightest cluster to its largest void until that is the same pixel
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut pattern = vec![false; n];
+        let mut energy = vec![0.0; n];
+        let initial = n / 10;
+        let mut count = 0;
+        while count < initial {
+            let p = rng.gen_range(0..n);
+            if !pattern[p] {
+                pattern[p] = true;
+                update(&mut energy, p, 1.0);
+                count += 1;
+            }
+        }
+        loop {
+            let cluster = find(&energy, &pattern, true);
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+            let void = find(&energy, &pattern, false);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut rank = vec![0; n];
+        //the initial pixels are ranked by removing the tightest clusters first
+        let mut removing = pattern.clone();
+        let mut removing_energy = energy.clone();
+        for r in (0..initial).rev() {
+            let cluster = find(&removing_energy, &removing, true);
+            removing[cluster] = false;
+            update(&mut removing_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        //the rest by filling the largest voids
+        for r in initial..n {
+            let void = find(&energy, &pattern, false);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+
+        rank.into_iter().map(|r| (r as f64 + 0.5) / n as f64).collect()
+    })
+}
+
+//golden ratio sequence shifted by a blue noise mask, so the error of neighbouring pixels differs as
+//much as possible and shows as fine grain instead of blotches. every dimension reads the mask at
+//its own offset
+pub struct BlueNoiseSampler {
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u32) -> BlueNoiseSampler {
+        BlueNoiseSampler { state: SampleState { seed, ..SampleState::default() } }
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next();
+        let offset = hash(&[dimension, 4, self.state.seed]) as usize;
+        let x = (self.state.pixel.0 as usize + offset) % BLUE_NOISE_SIZE;
+        let y = (self.state.pixel.1 as usize + (offset >> 16)) % BLUE_NOISE_SIZE;
+        let value = blue_noise_mask()[y * BLUE_NOISE_SIZE + x] + self.state.index as f64 * 0.6180339887498949;
+        value - value.floor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //largest gap between sorted values in [0, 1), including the ends
+    fn largest_gap(mut values: Vec<f64>) -> f64 {
+        values.sort_by(f64::total_cmp);
+        let mut gap = values[0].max(1.0 - values[values.len() - 1]);
+        for pair in values.windows(2) {
+            gap = gap.max(pair[1] - pair[0]);
+        }
+        gap
+    }
+
+    #[test]
+    fn samples_fill_strata() {
+        let n = 16;
+        for kind in [SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise] {
+            let mut sampler = create(kind, n, 0);
+            let mut values = vec![Vec::new(); 6];
+            for index in 0..n {
+                sampler.start_pixel_sample((3, 7), index);
+                for dimension in values.iter_mut() {
+                    let value = sampler.get_1d();
+                    assert!((0.0..1.0).contains(&value));
+                    dimension.push(value);
+                }
+            }
+            //independent samples would leave gaps of about 3 / n
+            for dimension in values {
+                assert!(largest_gap(dimension) < 2.0 / n as f64, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_repeatable() {
+        let mut a = SobolSampler::new(1);
+        let mut b = SobolSampler::new(1);
+        a.start_pixel_sample((1, 2), 5);
+        b.start_pixel_sample((1, 2), 5);
+        assert_eq!(a.get_2d(), b.get_2d());
+        //other pixels are scrambled differently
+        a.start_pixel_sample((1, 2), 5);
+        b.start_pixel_sample((2, 2), 5);
+        assert_ne!(a.get_2d(), b.get_2d());
+        //and so are other seeds
+        let mut c = SobolSampler::new(2);
+        a.start_pixel_sample((1, 2), 5);
+        c.start_pixel_sample((1, 2), 5);
+        assert_ne!(a.get_2d(), c.get_2d());
+    }
+
+    #[test]
+    fn halton_dimensions_past_the_primes_are_not_repeated() {
+        let mut sampler = HaltonSampler::new(0);
+        let offsets: Vec<f64> = (0..16).map(|index| {
+            sampler.start_pixel_sample((3, 7), index);
+            let values: Vec<f64> = (0..=PRIMES.len()).map(|_| sampler.get_1d()).collect();
+            (values[PRIMES.len()] - values[0]).rem_euclid(1.0)
+        }).collect();
+        //with the bases wrapped around, the last dimension would be the first shifted by a constant
+        assert!(offsets.iter().any(|&offset| (offset - offsets[0]).abs() > 1e-9));
+    }
+
+    #[test]
+    fn permutation_is_bijective() {
+        let mut seen: Vec<u32> = (0..37).map(|i| permutation_element(i, 37, 12345)).collect();
+        seen.sort();
+        assert_eq!(seen, (0..37).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn blue_noise_ranks_are_unique() {
+        let mut ranks = blue_noise_mask().to_vec();
+        ranks.sort_by(f64::total_cmp);
+        ranks.dedup();
+        assert_eq!(ranks.len(), BLUE_NOISE_SIZE * BLUE_NOISE_SIZE);
+    }
+}
@@ -0,0 +1,226 @@
+use raytracer::PI;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::material::{Material, Ior};
+use crate::vec3::{Color, Point3};
+use crate::sphere::Sphere;
+use crate::rectangle::{RectangleXY, RectangleXZ};
+use crate::principled::Principled;
+use crate::layered::Coating;
+use crate::spectrum::{Spectrum, ILLUMINANT_D65};
+use crate::medium::{Medium, Phase};
+use crate::constant_medium::ConstantMedium;
+use crate::heterogeneous_medium::{DensityGrid, HeterogeneousMedium};
+use crate::texture::Texture;
+use crate::thin_film::ThinFilm;
+use crate::light::Light;
+use crate::light_sampler::LightList;
+use crate::config::Config;
+use crate::sky::{Sky, SkyModel, PreethamSky};
+use crate::{SKY_SCALE, SUN_IRRADIANCE};
+use std::path::Path;
+
+//everything rays are traced against
+pub struct Scene {
+    pub world: HittableList,
+    //shapes of the emitters in world, sampled for direct lighting
+    pub lights: LightList,
+    //point, spot and directional lights
+    pub analytic_lights: Vec<Light>,
+    //medium filling the space between objects, e.g. atmospheric fog
+    pub fog: Option<Medium>,
+    pub sky: Sky,
+    //paths carry radiance at sampled wavelengths instead of rgb, see SampledWavelengths
+    pub spectral: bool,
+}
+
+//scene chosen in the configuration. the default is the two objects the renderer started out with,
+//the others add demo content for the features that came after
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SceneKind {
+    Default,
+    //spheres of glass with dispersion
+    Glass,
+    //principled, layered, subsurface and thin film materials
+    Materials,
+    //clouds of participating media and fog
+    Volumes,
+    //blackbody, tabulated, colored and punctual emitters
+    Lights,
+    //all of the above at once
+    Showcase,
+}
+
+type Objects = Vec<Box<dyn Hittable + Send + Sync>>;
+
+pub fn build(kind: SceneKind, config: &Config) -> Scene {
+    //create world item list
+    let mut list: Objects = Vec::new();
+    //emitters that are sampled directly, they are also part of the world list
+    let mut lights: Objects = Vec::new();
+    //punctual lights, and the sun of the physical sky
+    let mut analytic_lights = Vec::new();
+    let mut fog = None;
+
+    //define some materials
+    let material_green_metall = Material::Metallic { albedo: (Color::new(0.28,0.95,0.55)), fuzz: (0.0), film: None };
+    let material_blue_metall = Material::Metallic { albedo: Color::new(0.5, 0.45, 0.75), fuzz: 0.2, film: None };
+    
+    //add spheres and rectangles to world item list
+    
+    list.push( Box::new( Sphere::new(Point3::new( 1.0, 2.5, 10.0), 2.5, material_green_metall ) ) );
+    list.push( Box::new( RectangleXZ::new(-100.0, 100.0, -100.0, 100.0, 0.0, material_blue_metall ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new( 0.0, 0.0,    -1.0), 0.5,   material_center ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new(-1.0, 0.0,    -1.0), 0.5,   material_left   ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new( 1.0, 0.0,    -1.0), 0.5,   material_right  ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new( -0.5,1.0,    -1.2), 0.5,   material_up     ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new( 1.3, 0.5,    -2.5), 0.8,   material_behind ) ) );
+    //list.push( Box::new( Sphere::new(Point3::new( 0.0, 0.0,    -0.25), 0.1,   material_pink_glass ) ) );
+    //list.push( Box::new( Rectangle_xy::new(-1.0, 1.0, 0.1, 0.6, -1.0, material_ground ) ) );
+    //list.push( Box::new( Rectangle_yz::new(0.0, 1.0, -1.0, 1.0, 0.0, material_center ) ) );
+    
+    
+    let showcase = kind == SceneKind::Showcase;
+    if showcase || kind == SceneKind::Glass {
+        glass(&mut list);
+    }
+    if showcase || kind == SceneKind::Materials {
+        materials(&mut list);
+    }
+    if showcase || kind == SceneKind::Volumes {
+        fog = Some(volumes(&mut list, config.volume.as_deref()));
+    }
+    if showcase || kind == SceneKind::Lights {
+        emitters(&mut list, &mut lights, &mut analytic_lights);
+    }
+
+    let sky = match config.sky_model {
+        SkyModel::Gradient => Sky::Gradient,
+        SkyModel::Preetham => Sky::Preetham(Box::new(PreethamSky::new(config.sun_elevation, config.sun_azimuth, config.turbidity, SKY_SCALE, SUN_IRRADIANCE))),
+    };
+
+    Scene { world: HittableList::new(list), lights: LightList::new(lights, config.light_selection), analytic_lights, fog, sky, spectral: config.spectral }
+}
+
+//spheres of glass, diamond and water that split light into its colors
+fn glass(list: &mut Objects) {
+    let material_glass = Material::Dielectric { albedo: Color::new(0.8, 0.90, 0.81), index_of_refraction: Ior::Constant(1.5), film: None };
+    let material_crown_glass = Material::Dielectric { albedo: Color::new(0.95, 0.95, 0.95), index_of_refraction: Ior::BK7, film: None };
+    let material_blue = Material::Lambertian { albedo: Color::new(0.1, 0.2, 0.8) };
+    let material_pink_glass = Material::Dielectric { albedo: Color::new(0.99, 0.3, 0.8), index_of_refraction: Ior::DIAMOND, film: None };
+    let material_water = Material::Dielectric { albedo: Color::new(0.95, 0.97, 1.0), index_of_refraction: Ior::WATER, film: None };
+    let material_ground = Material::Lambertian { albedo: Color::new(0.7,0.8,0.3) };
+
+    list.push( Box::new( Sphere::new(Point3::new(-3.5, 1.5,  8.0), 1.5, material_glass ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 3.8, 1.0,  6.5), 1.0, material_pink_glass ) ) );
+    list.push( Box::new( Sphere::new(Point3::new(-1.0, 0.6,  5.0), 0.6, material_water ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 1.6, 0.8,  4.5), 0.8, material_crown_glass ) ) );
+    list.push( Box::new( Sphere::new(Point3::new(-6.5, 2.0, 14.0), 2.0, material_blue ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 6.0, 1.5, 13.0), 1.5, material_ground ) ) );
+}
+
+//spheres of brushed gold, car paint, frosted glass, varnished wood, wax, marble, a soap bubble and
+//anodized titanium
+fn materials(list: &mut Objects) {
+    let material_brushed_gold = Material::Principled(Principled {
+        metallic: 1.0,
+        roughness: 0.35,
+        anisotropic: 0.8,
+        ..Principled::new(Color::new(1.0, 0.78, 0.34))
+    });
+    let material_car_paint = Material::Principled(Principled {
+        roughness: 0.6,
+        clearcoat: 1.0,
+        clearcoat_gloss: 0.9,
+        sheen: 0.3,
+        specular_tint: 0.5,
+        ..Principled::new(Color::new(0.6, 0.05, 0.05))
+    });
+    let material_frosted_glass = Material::Principled(Principled {
+        transmission: 1.0,
+        roughness: 0.25,
+        ior: 1.45,
+        ..Principled::new(Color::new(0.9, 0.95, 1.0))
+    });
+    let material_varnished_wood = Material::Layered {
+        base: Box::new(Material::Lambertian { albedo: Color::new(0.45, 0.25, 0.1) }),
+        coating: Coating { ior: 1.5, roughness: 0.05, thickness: 0.2, absorption: Color::new(0.2, 0.6, 1.5) },
+    };
+    let material_wax = Material::Subsurface {
+        albedo: Color::new(0.99, 0.95, 0.8),
+        mean_free_path: Color::new(0.4, 0.25, 0.1),
+        ior: 1.45,
+    };
+    let material_marble = Material::Subsurface {
+        albedo: Color::new(0.999, 0.998, 0.995),
+        mean_free_path: Color::new(0.08, 0.07, 0.06),
+        ior: 1.5,
+    };
+    let material_soap_bubble = Material::Dielectric {
+        albedo: Color::WHITE,
+        index_of_refraction: Ior::Constant(1.0),
+        film: Some(ThinFilm { ior: 1.33, thickness: 400.0, thickness_texture: Some(Texture::marble(5, 1.5, 150.0, 900.0)) }),
+    };
+    let material_anodized_titanium = Material::Metallic {
+        albedo: Color::new(0.55, 0.5, 0.45),
+        fuzz: 0.05,
+        film: Some(ThinFilm { ior: 2.4, thickness: 120.0, thickness_texture: None }),
+    };
+
+    list.push( Box::new( Sphere::new(Point3::new(-3.0, 1.0, 14.5), 1.0, material_brushed_gold ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 3.5, 1.2, 16.0), 1.2, material_car_paint ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 4.5, 0.7,  9.5), 0.7, material_frosted_glass ) ) );
+    list.push( Box::new( Sphere::new(Point3::new(-3.0, 0.7,  4.5), 0.7, material_varnished_wood ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 1.8, 0.45, 3.3), 0.45, material_wax ) ) );
+    list.push( Box::new( Sphere::new(Point3::new(-1.9, 0.45, 3.2), 0.45, material_marble ) ) );
+    list.push( Box::new( Sphere::new(Point3::new(-0.2, 1.9,  4.0), 0.6, material_soap_bubble ) ) );
+    list.push( Box::new( Sphere::new(Point3::new( 6.0, 0.8,  8.5), 0.8, material_anodized_titanium ) ) );
+}
+
+//a cloud of constant density and one read from the density grid at volume, or generated without one,
+//in a thin haze that is returned
+fn volumes(list: &mut Objects, volume: Option<&Path>) -> Medium {
+    let cloud_boundary = Sphere::new(Point3::new(-1.5, 4.8, 15.0), 1.3, Material::Lambertian { albedo: Color::WHITE });
+    list.push( Box::new( ConstantMedium::new(Box::new(cloud_boundary), 2.0, Color::new(0.95, 0.95, 0.95), Phase::HenyeyGreenstein { g: 0.3 }) ) );
+    let cloud_grid = match volume {
+        Some(file) => match DensityGrid::load(file) {
+            Err(why) => panic!("couldn't load {}: {}", file.display(), why),
+            Ok(grid) => grid,
+        },
+        None => DensityGrid::cloud(64, 1),
+    };
+    list.push( Box::new( HeterogeneousMedium::new(Point3::new(2.5, 3.5, 16.0), Point3::new(7.5, 6.5, 19.0), cloud_grid, 6.0, Color::new(0.97, 0.97, 0.97), Phase::HenyeyGreenstein { g: 0.5 }) ) );
+
+    //thin haze filling the whole scene
+    Medium { sigma_a: Color::new(0.0005, 0.0005, 0.0005), sigma_s: Color::new(0.004, 0.0045, 0.005), phase: Phase::HenyeyGreenstein { g: 0.6 } }
+}
+
+//a blackbody lamp, a daylight panel with a wall of colored leds below it, and a point and a spot light
+fn emitters(list: &mut Objects, lights: &mut Objects, analytic_lights: &mut Vec<Light>) {
+    let material_lamp = Material::DiffuseLight { emission: Spectrum::blackbody(2700.0, 8.0) };
+    let material_daylight_panel = Material::DiffuseLight { emission: Spectrum::tabulated(&ILLUMINANT_D65, 0.04) };
+
+    let lamp = Sphere::new(Point3::new( 0.4, 0.4,  7.0), 0.4, material_lamp );
+    lights.push( Box::new( lamp.clone() ) );
+    list.push( Box::new( lamp ) );
+    let daylight_panel = RectangleXY::new(-4.0, 4.0, 5.5, 7.0, 20.0, material_daylight_panel );
+    lights.push( Box::new( daylight_panel.clone() ) );
+    list.push( Box::new( daylight_panel ) );
+    //wall of small led panels below the daylight panel
+    for i in 0..12 {
+        for j in 0..3 {
+            let x = -3.0 + i as f64 * 0.5;
+            let y = 4.0 + j as f64 * 0.45;
+            let hue = i as f64 / 12.0;
+            let channel = |offset: f64| 0.5 + 0.5 * (2.0 * PI * (hue - offset)).cos();
+            let color = Color::new(channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0));
+            let brightness = if (i + j) % 5 == 0 {12.0} else {2.0};
+            let led = RectangleXY::new(x + 0.05, x + 0.45, y, y + 0.35, 20.0, Material::DiffuseLight { emission: Spectrum::Rgb(brightness * color) });
+            lights.push( Box::new( led.clone() ) );
+            list.push( Box::new( led ) );
+        }
+    }
+
+    analytic_lights.push(Light::Point { position: Point3::new(-5.0, 4.0, 6.0), intensity: Spectrum::blackbody(3200.0, 3.0) });
+    analytic_lights.push(Light::spot(Point3::new(4.0, 6.0, 12.0), Point3::new(3.5, 1.2, 16.0), Spectrum::Rgb(Color::new(6.0, 5.5, 5.0)), 25.0, 15.0));
+}
@@ -0,0 +1,197 @@
+use raytracer::{deg_to_rad, PI};
+use crate::vec3::{Vec3, Color, unit_vector, dot};
+use crate::spectrum::{Spectrum, SampledWavelengths, spectrum_to_xyz, xyz_to_output_rgb};
+use crate::light::Light;
+
+//angular diameter of the sun seen from earth, in degrees
+const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+const SUN_TEMPERATURE: f64 = 5778.0;
+
+//sky model chosen in the configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SkyModel {
+    Gradient,
+    Preetham,
+}
+
+//light arriving from directions where rays leave the scene
+#[derive(Debug, Clone)]
+pub enum Sky {
+    //blend from white at the horizon to light blue at the top
+    Gradient,
+    Preetham(Box<PreethamSky>),
+}
+
+impl Sky {
+    //light arriving along direction, including the disk of the sun
+    pub fn radiance(&self, direction: Vec3, lambda: &SampledWavelengths) -> Color {
+        match *self {
+            Sky::Gradient => {
+                let t = 0.5 * (unit_vector(direction).y() + 1.0);
+                lambda.emission(&Spectrum::Rgb((1.0 - t) * Color::new(1.0, 1.0 , 1.0) + t * Color::new(0.5, 0.7, 1.0)))
+            }
+            Sky::Preetham(ref sky) => lambda.emission(&Spectrum::Rgb(sky.radiance(direction))) + sky.sun.radiance(direction, lambda),
+        }
+    }
+
+    //directional light for the sun whose disk the sky shows, sampled for direct lighting
+    pub fn sun(&self) -> Option<&Light> {
+        match self {
+            Sky::Gradient => None,
+            Sky::Preetham(sky) => Some(&sky.sun),
+        }
+    }
+}
+
+//analytic daylight model of Preetham, Shirley and Smits. Angles are in degrees, the sun's azimuth is
+//measured from +z towards +x and turbidity goes from 2 for a clear to about 10 for a hazy sky
+#[derive(Debug, Clone)]
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    theta_sun: f64,
+    //luminance and chromaticity at the zenith
+    zenith: [f64; 3],
+    //Perez coefficients for luminance and chromaticity
+    perez: [[f64; 5]; 3],
+    //luminance of the model is in kcd/m^2, scaled to the scene's units
+    scale: f64,
+    sun: Light,
+}
+
+impl PreethamSky {
+    //sun_irradiance is the luminance the sun would have outside of the atmosphere
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, scale: f64, sun_irradiance: f64) -> PreethamSky {
+        let (elevation, azimuth) = (deg_to_rad(elevation), deg_to_rad(azimuth));
+        let sun_direction = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos());
+        let t = turbidity;
+        let theta = PI / 2.0 - elevation;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| ((c[0] * theta + c[1]) * theta + c[2]) * theta + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y_chroma = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        PreethamSky {
+            sun_direction,
+            theta_sun: theta,
+            zenith: [zenith_y.max(0.0), zenith_x, zenith_y_chroma],
+            perez,
+            scale,
+            sun: sun(sun_direction, theta, turbidity, sun_irradiance),
+        }
+    }
+
+    //rgb radiance of the sky around the sun, directions below the horizon see the sky at the horizon
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let direction = unit_vector(direction);
+        let cos_theta = direction.y().max(0.001);
+        let gamma = dot(direction, self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let [big_y, x, y] = [0, 1, 2].map(|i| {
+            let p = &self.perez[i];
+            let perez = |cos_theta: f64, gamma: f64| {
+                (1.0 + p[0] * (p[1] / cos_theta).exp()) * (1.0 + p[2] * (p[3] * gamma).exp() + p[4] * gamma.cos().powi(2))
+            };
+            self.zenith[i] * perez(cos_theta, gamma) / perez(1.0, self.theta_sun)
+        });
+
+        let big_y = big_y * self.scale;
+        let xyz = Vec3::new(x / y * big_y, big_y, (1.0 - x - y) / y * big_y);
+        xyz_to_output_rgb(xyz)
+    }
+
+}
+
+//directional light for the sun disk at theta_sun from the zenith, reddened by the atmosphere it
+//shines through. irradiance is the luminance it would have outside of the atmosphere
+fn sun(direction: Vec3, theta_sun: f64, turbidity: f64, irradiance: f64) -> Light {
+    //relative optical mass of the atmosphere along the sun's direction
+    let theta_deg = theta_sun.to_degrees().min(93.0);
+    let mass = 1.0 / (theta_sun.cos().max(0.0) + 0.15 * (93.885 - theta_deg).powf(-1.253));
+
+    //rayleigh scattering and aerosols with Angstrom's formula, wavelengths in micrometers
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |lambda: f64| {
+        let l = lambda / 1000.0;
+        (-0.008735 * l.powf(-4.08) * mass).exp() * (-beta * l.powf(-1.3) * mass).exp()
+    };
+
+    let blackbody = Spectrum::blackbody(SUN_TEMPERATURE, 1.0);
+    let (r, g, b) = xyz_to_output_rgb(spectrum_to_xyz(|l| blackbody.value(l) * transmittance(l))).get_components();
+    //a low sun can be more saturated than the output gamut
+    let color = Color::new(r.max(0.0), g.max(0.0), b.max(0.0));
+    let unattenuated = spectrum_to_xyz(|l| blackbody.value(l)).y();
+
+    Light::Directional {
+        direction,
+        irradiance: Spectrum::Rgb(irradiance / unattenuated * color),
+        angular_diameter: SUN_ANGULAR_DIAMETER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onb::Onb;
+
+    fn sun_color(sky: &PreethamSky) -> Color {
+        match sky.sun {
+            Light::Directional { irradiance: Spectrum::Rgb(color), .. } => color,
+            _ => panic!("sun should be a directional light"),
+        }
+    }
+
+    #[test]
+    fn preetham_brighter_towards_sun() {
+        let sky = PreethamSky::new(30.0, 90.0, 3.0, 1.0, 1.0);
+        let towards = sky.radiance(Vec3::new(1.0, 0.7, 0.0));
+        let away = sky.radiance(Vec3::new(-1.0, 0.7, 0.0));
+        assert!(towards.y() > away.y());
+        assert!(away.x() > 0.0 && away.y() > 0.0 && away.z() > 0.0);
+    }
+
+    #[test]
+    fn preetham_clear_sky_is_blue() {
+        let sky = PreethamSky::new(60.0, 0.0, 2.5, 1.0, 1.0);
+        let zenith = sky.radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z() > zenith.x());
+    }
+
+    #[test]
+    fn sunset_is_red() {
+        let noon = sun_color(&PreethamSky::new(70.0, 0.0, 3.0, 1.0, 1.0));
+        let sunset = sun_color(&PreethamSky::new(3.0, 0.0, 3.0, 1.0, 1.0));
+        assert!(sunset.x() / sunset.z() > noon.x() / noon.z());
+        assert!(sunset.y() < noon.y());
+    }
+
+    #[test]
+    fn sun_disk_is_drawn() {
+        let sky = Sky::Preetham(Box::new(PreethamSky::new(40.0, 30.0, 3.0, 1.0, 1.0)));
+        let lambda = SampledWavelengths::new(550.0, false);
+        let sun = sky.sun().unwrap().clone();
+        let direction = match sun {
+            Light::Directional { direction, .. } => direction,
+            _ => panic!("sun should be a directional light"),
+        };
+        let outside = Onb::build_from_w(direction).local(Vec3::new(deg_to_rad(0.3).sin(), 0.0, deg_to_rad(0.3).cos()));
+        let sky_only = sky.radiance(outside, &lambda);
+        let disk = sky.radiance(direction, &lambda) - sky_only;
+
+        //the disk brings the light's irradiance over its solid angle
+        let sample = sun.sample(Vec3::origin(), &lambda, (0.5, 0.5)).unwrap();
+        assert!((disk / sun.pdf(direction) - sample.radiance).length() < 1e-3 * sample.radiance.length());
+        assert!(disk.y() > 100.0 * sky_only.y());
+    }
+}
@@ -0,0 +1,348 @@
+use std::sync::OnceLock;
+use crate::vec3::{Vec3, Color};
+use crate::rgb_spectrum::RgbSpectrum;
+
+//visible range camera paths sample their wavelength from, in nm
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+//Fraunhofer d line, the wavelength nominal indices of refraction are quoted at
+pub const LAMBDA_D: f64 = 587.6;
+
+//step used when integrating spectra over the visible range
+pub const LAMBDA_STEP: f64 = 5.0;
+pub const LAMBDA_SAMPLES: usize = ((LAMBDA_MAX - LAMBDA_MIN) / LAMBDA_STEP) as usize;
+
+//CIE standard illuminant D65, relative spectral power in 10 nm steps
+pub const ILLUMINANT_D65: [(f64, f64); 41] = [
+    (380.0, 49.9755), (390.0, 54.6482), (400.0, 82.7549), (410.0, 91.4860), (420.0, 93.4318),
+    (430.0, 86.6823), (440.0, 104.865), (450.0, 117.008), (460.0, 117.812), (470.0, 114.861),
+    (480.0, 115.923), (490.0, 108.811), (500.0, 109.354), (510.0, 107.802), (520.0, 104.790),
+    (530.0, 107.689), (540.0, 104.405), (550.0, 104.046), (560.0, 100.000), (570.0, 96.3342),
+    (580.0, 95.7880), (590.0, 88.6856), (600.0, 90.0062), (610.0, 89.5991), (620.0, 87.6987),
+    (630.0, 83.2886), (640.0, 83.6992), (650.0, 80.0268), (660.0, 80.2146), (670.0, 82.2778),
+    (680.0, 78.2842), (690.0, 69.7213), (700.0, 71.6091), (710.0, 74.3490), (720.0, 61.6040),
+    (730.0, 69.8856), (740.0, 75.0870), (750.0, 63.5927), (760.0, 46.4182), (770.0, 66.8054),
+    (780.0, 63.3828),
+];
+
+//piecewise gaussian with different widths left and right of the mean
+fn gaussian(lambda: f64, mu: f64, sigma_left: f64, sigma_right: f64) -> f64 {
+    let sigma = if lambda < mu {sigma_left} else {sigma_right};
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+//CIE 1931 2° color matching functions, multi lobe fit by Wyman, Sloan and Shirley
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8);
+
+    Vec3::new(x, y, z)
+}
+
+//converts CIE XYZ to linear sRGB (D65 white point)
+pub fn xyz_to_rgb(xyz: Vec3) -> Color {
+    let (x, y, z) = xyz.get_components();
+    Color::new(
+         3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+         0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+//midpoint of the i-th integration step
+pub fn lambda_sample(i: usize) -> f64 {
+    LAMBDA_MIN + (i as f64 + 0.5) * LAMBDA_STEP
+}
+
+fn cie_y_integral() -> f64 {
+    static INTEGRAL: OnceLock<f64> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| {
+        (0..LAMBDA_SAMPLES).map(|i| cie_xyz(lambda_sample(i)).y() * LAMBDA_STEP).sum()
+    })
+}
+
+//integrates a spectral distribution against the color matching functions,
+//normalized so that a constant spectrum of 1 has Y = 1
+pub fn spectrum_to_xyz(f: impl Fn(f64) -> f64) -> Vec3 {
+    let mut xyz = Vec3::origin();
+    for i in 0..LAMBDA_SAMPLES {
+        let lambda = lambda_sample(i);
+        xyz += f(lambda) * cie_xyz(lambda);
+    }
+    xyz * (LAMBDA_STEP / cie_y_integral())
+}
+
+//linearly interpolates tabulated (wavelength, value) pairs, zero outside the table
+fn interpolate(samples: &[(f64, f64)], lambda: f64) -> f64 {
+    match samples.iter().position(|&(l, _)| l >= lambda) {
+        Some(0) if samples[0].0 == lambda => samples[0].1,
+        Some(0) | None => 0.0,
+        Some(i) => {
+            let (l0, v0) = samples[i - 1];
+            let (l1, v1) = samples[i];
+            v0 + (v1 - v0) * (lambda - l0) / (l1 - l0)
+        }
+    }
+}
+
+//D65 scaled to a luminance of 1, the white of the output color space
+pub fn d65(lambda: f64) -> f64 {
+    static LUMINANCE: OnceLock<f64> = OnceLock::new();
+    let luminance = LUMINANCE.get_or_init(|| spectrum_to_xyz(|l| interpolate(&ILLUMINANT_D65, l)).y());
+    interpolate(&ILLUMINANT_D65, lambda) / luminance
+}
+
+//converts CIE XYZ to the output color space, linear sRGB balanced so that D65 maps to white
+pub fn xyz_to_output_rgb(xyz: Vec3) -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    let white = WHITE.get_or_init(|| xyz_to_rgb(spectrum_to_xyz(d65)));
+    xyz_to_rgb(xyz) / *white
+}
+
+//average rgb response over the sampled range, used to keep undispersed light white
+fn rgb_normalization() -> Color {
+    static NORMALIZATION: OnceLock<Color> = OnceLock::new();
+    *NORMALIZATION.get_or_init(|| {
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as u32;
+        let mut sum = Color::BLACK;
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + i as f64 + 0.5;
+            sum += xyz_to_rgb(cie_xyz(lambda));
+        }
+        sum / steps as f64
+    })
+}
+
+//rgb weight of a path that only carries a single wavelength,
+//averaging it over uniformly sampled wavelengths gives white
+pub fn wavelength_to_rgb(lambda: f64) -> Color {
+    xyz_to_rgb(cie_xyz(lambda)) / rgb_normalization()
+}
+
+//spectral radiance of a black body at the given temperature in kelvin
+fn planck(lambda: f64, temperature: f64) -> f64 {
+    const C: f64 = 299792458.0;
+    const H: f64 = 6.62606957e-34;
+    const KB: f64 = 1.3806488e-23;
+
+    let l = lambda * 1e-9;
+    (2.0 * H * C * C) / (l.powi(5) * ((H * C / (l * KB * temperature)).exp() - 1.0))
+}
+
+//emission spectrum of a light source
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum Spectrum {
+    Rgb(Color),
+    //black body at a temperature in kelvin, normalized to a peak value of scale
+    Blackbody { temperature: f64, scale: f64, rgb: Color },
+    //measured spectral power distribution as (wavelength in nm, value) pairs
+    Tabulated { samples: &'static [(f64, f64)], scale: f64, rgb: Color },
+}
+
+impl Spectrum {
+    //the rgb of the spectral variants is integrated once here, for rendering in rgb
+    pub fn blackbody(temperature: f64, scale: f64) -> Spectrum {
+        Spectrum::Blackbody { temperature, scale, rgb: Color::BLACK }.with_rgb()
+    }
+
+    pub fn tabulated(samples: &'static [(f64, f64)], scale: f64) -> Spectrum {
+        Spectrum::Tabulated { samples, scale, rgb: Color::BLACK }.with_rgb()
+    }
+
+    fn with_rgb(mut self) -> Spectrum {
+        let color = xyz_to_output_rgb(spectrum_to_xyz(|l| self.value(l)));
+        if let Spectrum::Blackbody { ref mut rgb, .. } | Spectrum::Tabulated { ref mut rgb, .. } = self {
+            *rgb = color;
+        }
+        self
+    }
+
+    //value at a wavelength, rgb spectra have no unique spectral value and give 0
+    pub fn value(&self, lambda: f64) -> f64 {
+        match *self {
+            Spectrum::Rgb(_) => 0.0,
+            Spectrum::Blackbody { temperature, scale, .. } => {
+                //Wien's displacement law gives the wavelength of the peak
+                let peak = 2.8977721e-3 / temperature * 1e9;
+                scale * planck(lambda, temperature) / planck(peak, temperature)
+            }
+            Spectrum::Tabulated { samples, scale, .. } => scale * interpolate(samples, lambda),
+        }
+    }
+
+    pub fn to_rgb(self) -> Color {
+        match self {
+            Spectrum::Rgb(rgb) | Spectrum::Blackbody { rgb, .. } | Spectrum::Tabulated { rgb, .. } => rgb,
+        }
+    }
+
+    //luminance of the spectrum's rgb
+    pub fn luminance(self) -> f64 {
+        let (r, g, b) = self.to_rgb().get_components();
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+}
+
+//wavelengths carried by a camera path. In spectral mode the three components of
+//a Color along the path hold radiance at these wavelengths instead of rgb
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct SampledWavelengths {
+    lambda: [f64; 3],
+    spectral: bool,
+}
+
+impl SampledWavelengths {
+    //hero wavelength sampling, the others are evenly rotated through the visible range
+    pub fn new(hero: f64, spectral: bool) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = [0.0, 1.0, 2.0].map(|i| {
+            let l = hero + i * range / 3.0;
+            if l >= LAMBDA_MAX {l - range} else {l}
+        });
+        SampledWavelengths { lambda, spectral }
+    }
+
+    fn sample(&self, f: impl Fn(f64) -> f64) -> Color {
+        Color::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+
+    //brings an rgb reflectance or attenuation into the path's representation
+    pub fn reflectance(&self, rgb: Color) -> Color {
+        if !self.spectral {
+            return rgb
+        }
+        let s = RgbSpectrum::new(rgb);
+        self.sample(|l| s.at(l))
+    }
+
+    //brings an emission spectrum into the path's representation
+    pub fn emission(&self, spectrum: &Spectrum) -> Color {
+        if !self.spectral {
+            return spectrum.to_rgb()
+        }
+        match *spectrum {
+            Spectrum::Rgb(color) => {
+                let s = RgbSpectrum::illuminant(color);
+                self.sample(|l| s.at(l) * d65(l))
+            }
+            _ => self.sample(|l| spectrum.value(l)),
+        }
+    }
+
+    //weight applied once a dispersive interface leaves only the hero wavelength
+    pub fn collapse(&self) -> Color {
+        if self.spectral {
+            Color::new(3.0, 0.0, 0.0)
+        } else {
+            wavelength_to_rgb(self.lambda[0])
+        }
+    }
+
+    //converts radiance carried by the path to output rgb
+    pub fn to_rgb(self, radiance: Color) -> Color {
+        if !self.spectral {
+            return radiance
+        }
+        let values = [radiance.x(), radiance.y(), radiance.z()];
+        let mut xyz = Vec3::origin();
+        for (value, lambda) in values.into_iter().zip(self.lambda) {
+            xyz += value * cie_xyz(lambda);
+        }
+        //wavelengths are sampled uniformly, so the estimate is divided by their pdf
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        xyz_to_output_rgb(xyz / (3.0 * pdf * cie_y_integral()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cie_y_peak() {
+        let y = cie_xyz(555.0).y();
+        assert!((y - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn cie_outside_visible() {
+        let xyz = cie_xyz(900.0);
+        assert!(xyz.length() < 1e-3);
+    }
+
+    #[test]
+    fn wavelength_to_rgb_averages_to_white() {
+        let steps = 4000;
+        let mut sum = Color::BLACK;
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f64 + 0.5) / steps as f64;
+            sum += wavelength_to_rgb(lambda);
+        }
+        let avg = sum / steps as f64;
+        assert!((avg - Color::WHITE).length() < 1e-3);
+    }
+
+    #[test]
+    fn wavelength_to_rgb_red() {
+        let (r, g, b) = wavelength_to_rgb(650.0).get_components();
+        assert!(r > g && r > b);
+    }
+
+    #[test]
+    fn d65_is_white() {
+        let rgb = xyz_to_output_rgb(spectrum_to_xyz(d65));
+        assert!((rgb - Color::WHITE).length() < 1e-9);
+    }
+
+    #[test]
+    fn blackbody_color() {
+        let warm = Spectrum::blackbody(2700.0, 1.0).to_rgb();
+        let cool = Spectrum::blackbody(10000.0, 1.0).to_rgb();
+        assert!(warm.x() > warm.z());
+        assert!(cool.z() > cool.x());
+
+        //the rgb worked out when the spectrum was built is that of its values
+        let panel = Spectrum::tabulated(&ILLUMINANT_D65, 0.5);
+        assert_eq!(panel.to_rgb(), xyz_to_output_rgb(spectrum_to_xyz(|l| panel.value(l))));
+    }
+
+    #[test]
+    fn sampled_wavelengths_in_range() {
+        let lambda = SampledWavelengths::new(700.0, true);
+        for l in lambda.lambda {
+            assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&l));
+        }
+        assert_eq!(lambda.lambda[0], 700.0);
+    }
+
+    #[test]
+    fn spectral_emission_converges_to_rgb() {
+        let color = Color::new(0.5, 0.7, 1.0);
+        let steps = 2000;
+        let mut sum = Color::BLACK;
+        for i in 0..steps {
+            let hero = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f64 + 0.5) / steps as f64;
+            let lambda = SampledWavelengths::new(hero, true);
+            sum += lambda.to_rgb(lambda.emission(&Spectrum::Rgb(color)));
+        }
+        let avg = sum / steps as f64;
+        assert!((avg - color).length() < 0.02, "{:?}", avg);
+    }
+
+    #[test]
+    fn rgb_mode_passes_through() {
+        let lambda = SampledWavelengths::new(500.0, false);
+        let c = Color::new(0.2, 0.4, 0.6);
+        assert_eq!(lambda.reflectance(c), c);
+        assert_eq!(lambda.to_rgb(c), c);
+    }
+}
@@ -1,7 +1,10 @@
-use crate::material::Material;
+use raytracer::{INFINITY, PI};
+use crate::material::{Material, emitted_luminance};
 use crate::vec3::{Vec3, Point3, dot};
+use crate::onb::Onb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+#[derive(Clone)]
 pub struct Sphere {
     center: Point3,
     radius: f64,
@@ -16,7 +19,7 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
 
         let oc: Vec3 = r.origin - self.center;
         let a = r.direction.length_squared();
@@ -42,18 +45,71 @@ impl Hittable for Sphere {
         let mut ret_rec = HitRecord::default();
         ret_rec.t = root;
         ret_rec.point = r.at(ret_rec.t);
-        ret_rec.material = self.material;
+        ret_rec.material = &self.material;
 
         let outward_normal = (ret_rec.point - self.center) / self.radius;
         ret_rec.set_face_normal(r, outward_normal);
 
-        return Some(ret_rec);
+        Some(ret_rec)
+    }
+
+    //uniform over the cone of directions the sphere covers as seen from origin
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared || self.hit(&Ray::new(origin, direction), 0.001, INFINITY).is_none() {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: Point3, (u1, u2): (f64, f64)) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return Vec3::sample_unit_vector((u1, u2));
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + u1 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * u2;
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        Onb::build_from_w(direction).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
+
+    fn power(&self) -> f64 {
+        let area = 4.0 * PI * self.radius * self.radius;
+        PI * area * emitted_luminance(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64), side: f64) -> Option<HitRecord<'_>> {
+        let outward_normal = Vec3::sample_unit_vector(u);
+        Some(HitRecord::on_side(self.center + self.radius * outward_normal, outward_normal, &self.material, side))
+    }
+
+    fn surface_pdf(&self) -> f64 {
+        1.0 / (8.0 * PI * self.radius * self.radius)
+    }
+
+    fn bounding_box(&self) -> Option<(Point3, Point3)> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some((self.center - r, self.center + r))
+    }
+
+    fn material(&self) -> Option<&Material> {
+        Some(&self.material)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use raytracer::random_f64;
 
     #[test]
     fn sphere1() {
@@ -87,4 +143,19 @@ mod tests {
         assert_eq!(op_rec.expect("Should not be none!").front_face, true);
 
     }
+
+    #[test]
+    fn sphere_sampling() {
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 10.0), 2.0, Material::Lambertian { albedo: Vec3::origin() });
+        let origin = Vec3::origin();
+
+        //every sampled direction hits the sphere and has the density of the cone
+        let pdf = sphere.pdf_value(origin, sphere.random(origin, (random_f64(), random_f64())));
+        for _ in 0..1000 {
+            let direction = sphere.random(origin, (random_f64(), random_f64()));
+            assert!(sphere.hit(&Ray::new(origin, direction), 0.001, INFINITY).is_some());
+            assert!((sphere.pdf_value(origin, direction) - pdf).abs() < 1e-9);
+        }
+        assert_eq!(sphere.pdf_value(origin, Vec3::new(0.0, 0.0, -1.0)), 0.0);
+    }
 }
\ No newline at end of file
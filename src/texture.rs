@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use crate::perlin::Perlin;
+use crate::vec3::Point3;
+
+//scalar value varying over space
+#[derive(Debug, Clone)]
+pub enum Texture {
+    //bands between min and max swirled by noise, like the colors on a soap bubble
+    Marble {
+        perlin: Arc<Perlin>,
+        scale: f64,
+        min: f64,
+        max: f64, },
+}
+
+impl Texture {
+    pub fn marble(seed: u64, scale: f64, min: f64, max: f64) -> Texture {
+        Texture::Marble { perlin: Arc::new(Perlin::new(seed)), scale, min, max }
+    }
+
+    pub fn value(&self, p: Point3) -> f64 {
+        match *self {
+            Texture::Marble { ref perlin, scale, min, max } => {
+                let t = 0.5 * (1.0 + (scale * p.y() + 6.0 * perlin.fbm(scale * p, 5)).sin());
+                min + t * (max - min)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marble_in_range() {
+        let texture = Texture::marble(3, 2.0, 200.0, 800.0);
+        for i in 0..1000 {
+            let p = Point3::new(i as f64 * 0.37, i as f64 * -0.11, i as f64 * 0.05);
+            let value = texture.value(p);
+            assert!((200.0..=800.0).contains(&value));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use raytracer::PI;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::{Point3, Color};
+use crate::spectrum::{LAMBDA_SAMPLES, lambda_sample, wavelength_to_rgb};
+
+//thin transparent layer on a surface whose reflections interfere, giving iridescent colors
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    pub ior: f64,
+    //thickness in nm
+    pub thickness: f64,
+    //varies the thickness over the surface, overrides thickness
+    pub thickness_texture: Option<Texture>,
+}
+
+//what lies below the film
+#[derive(Debug, Clone, Copy)]
+pub enum Substrate {
+    Dielectric(f64),
+    //reflecting the given fraction of light, with the phase shift of a mirror
+    Conductor(Color),
+}
+
+impl ThinFilm {
+    pub fn thickness_at(&self, p: Point3) -> f64 {
+        match self.thickness_texture {
+            Some(ref texture) => texture.value(p),
+            None => self.thickness,
+        }
+    }
+
+    //reflectance of the film on substrate for light arriving at cos_theta from a medium with index
+    //n1. Gives the rgb reflectance integrated over the spectrum, or the one at the ray's own
+    //wavelength once its path is dispersed
+    pub fn reflectance(&self, r_in: &Ray, p: Point3, cos_theta: f64, n1: f64, substrate: Substrate) -> Color {
+        let thickness = self.thickness_at(p);
+        let channel = |lambda: f64, c: usize| {
+            let r23 = match substrate {
+                Substrate::Dielectric(n3) => Interface::Dielectric(n3),
+                Substrate::Conductor(albedo) => {
+                    let (r, g, b) = albedo.get_components();
+                    Interface::Mirror(-[r, g, b][c].clamp(0.0, 1.0).sqrt())
+                }
+            };
+            self.reflectance_at(cos_theta, n1, r23, thickness, lambda)
+        };
+
+        if r_in.dispersed {
+            let lambda = r_in.wavelength;
+            return Color::new(channel(lambda, 0), channel(lambda, 1), channel(lambda, 2));
+        }
+
+        let mut sum = Color::BLACK;
+        for i in 0..LAMBDA_SAMPLES {
+            let lambda = lambda_sample(i);
+            let weight = wavelength_to_rgb(lambda);
+            sum += Color::new(
+                channel(lambda, 0) * weight.x(),
+                channel(lambda, 1) * weight.y(),
+                channel(lambda, 2) * weight.z(),
+            );
+        }
+        sum / LAMBDA_SAMPLES as f64
+    }
+
+    //Airy reflectance of the film at one wavelength, averaged over both polarizations
+    fn reflectance_at(&self, cos1: f64, n1: f64, below: Interface, thickness: f64, lambda: f64) -> f64 {
+        let n2 = self.ior;
+        let sin2 = n1 / n2 * (1.0 - cos1 * cos1).max(0.0).sqrt();
+        if sin2 >= 1.0 {
+            return 1.0
+        }
+        let cos2 = (1.0 - sin2 * sin2).sqrt();
+
+        let (r12_s, r12_p) = fresnel_amplitudes(n1, cos1, n2, cos2);
+        let (r23_s, r23_p) = match below {
+            Interface::Dielectric(n3) => {
+                let sin3 = n2 / n3 * sin2;
+                if sin3 >= 1.0 {
+                    //no light enters the substrate, all of it comes back out of the film
+                    return 1.0
+                }
+                fresnel_amplitudes(n2, cos2, n3, (1.0 - sin3 * sin3).sqrt())
+            }
+            Interface::Mirror(r) => (r, r),
+        };
+
+        //phase difference between light reflected at the top and the bottom of the film
+        let cos_delta = (4.0 * PI * n2 * thickness * cos2 / lambda).cos();
+        let airy = |r12: f64, r23: f64| {
+            let cross = 2.0 * r12 * r23 * cos_delta;
+            (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+        };
+        0.5 * (airy(r12_s, r23_s) + airy(r12_p, r23_p))
+    }
+}
+
+//lower interface of the film for one color channel
+#[derive(Clone, Copy)]
+enum Interface {
+    Dielectric(f64),
+    //fixed amplitude reflection coefficient
+    Mirror(f64),
+}
+
+//amplitude reflection coefficients of s and p polarized light
+fn fresnel_amplitudes(n1: f64, cos1: f64, n2: f64, cos2: f64) -> (f64, f64) {
+    let s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    (s, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    fn film(ior: f64, thickness: f64) -> ThinFilm {
+        ThinFilm { ior, thickness, thickness_texture: None }
+    }
+
+    #[test]
+    fn vanishing_film_is_plain_fresnel() {
+        //without thickness the film does not change the reflectance of the substrate
+        let r = film(1.33, 0.0).reflectance_at(1.0, 1.0, Interface::Dielectric(1.5), 0.0, 550.0);
+        let r0 = ((1.0 - 1.5) / (1.0 + 1.5f64)).powi(2);
+        assert!((r - r0).abs() < 1e-9, "{} {}", r, r0);
+    }
+
+    #[test]
+    fn quarter_wave_coating() {
+        //a quarter wave layer with ior sqrt(1.5) removes reflection of glass at that wavelength
+        let n2 = 1.5f64.sqrt();
+        let r = film(n2, 550.0 / (4.0 * n2)).reflectance_at(1.0, 1.0, Interface::Dielectric(1.5), 550.0 / (4.0 * n2), 550.0);
+        assert!(r < 1e-9);
+    }
+
+    #[test]
+    fn soap_film_is_colored() {
+        let r_in = Ray::new(Vec3::origin(), Vec3::new(0.0, 0.0, -1.0));
+        let r = film(1.33, 300.0).reflectance(&r_in, Vec3::origin(), 1.0, 1.0, Substrate::Dielectric(1.0));
+        assert!((r.x() - r.y()).abs() > 0.01 || (r.y() - r.z()).abs() > 0.01);
+        assert!(r.x() <= 1.0 && r.y() <= 1.0 && r.z() <= 1.0);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use crate::adaptive::PixelStats;
+
+//width and height in pixels of the tiles the image is rendered in
+pub const TILE_SIZE: u32 = 32;
+
+//rectangle of pixels from x0, y0 up to but not including x1, y1, counted from the lower left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    //pixels of the tile, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |j| (x0..x1).map(move |i| (i, j)))
+    }
+}
+
+//tiles of size covering an image, the ones at the right and top edges are cut to fit
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(size as usize) {
+        for x0 in (0..width).step_by(size as usize) {
+            tiles.push(Tile { x0, y0, x1: (x0 + size).min(width), y1: (y0 + size).min(height) });
+        }
+    }
+    tiles
+}
+
+//work stealing queue of tiles. each worker starts with a run of neighbouring tiles, and once its own
+//are done takes from the far end of another's, so workers that got cheap tiles help the others finish
+pub struct TileQueue {
+    queues: Vec<Mutex<VecDeque<Tile>>>,
+}
+
+impl TileQueue {
+    pub fn new(tiles: Vec<Tile>, workers: usize) -> TileQueue {
+        let queue = TileQueue { queues: (0..workers.max(1)).map(|_| Mutex::new(VecDeque::new())).collect() };
+        queue.refill(tiles);
+        queue
+    }
+
+    //hands out tiles again, split between the workers as in new, for the next pass over the image
+    pub fn refill(&self, tiles: Vec<Tile>) {
+        let workers = self.queues.len();
+        let n = tiles.len();
+        let mut tiles = tiles.into_iter();
+        for (w, queue) in self.queues.iter().enumerate() {
+            let count = (w + 1) * n / workers - w * n / workers;
+            *queue.lock().unwrap() = tiles.by_ref().take(count).collect();
+        }
+    }
+
+    //next tile for worker, none once every tile has been handed out
+    pub fn pop(&self, worker: usize) -> Option<Tile> {
+        let workers = self.queues.len();
+        if let Some(tile) = self.queues[worker % workers].lock().unwrap().pop_front() {
+            return Some(tile);
+        }
+        (1..workers).find_map(|k| self.queues[(worker + k) % workers].lock().unwrap().pop_back())
+    }
+}
+
+//image the workers write finished tiles into, with the samples each pixel has taken so far. passes of
+//progressive rendering read the tile back and add to it
+pub struct Framebuffer {
+    width: u32,
+    pixels: Mutex<Vec<PixelStats>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer { width, pixels: Mutex::new(vec![PixelStats::new(); (width * height) as usize]) }
+    }
+
+    //pixels of tile in the order Tile::pixels visits them
+    pub fn read_tile(&self, tile: &Tile) -> Vec<PixelStats> {
+        let framebuffer = self.pixels.lock().unwrap();
+        tile.pixels().map(|(i, j)| framebuffer[(j * self.width + i) as usize]).collect()
+    }
+
+    pub fn write_tile(&self, tile: &Tile, pixels: &[PixelStats]) {
+        let mut framebuffer = self.pixels.lock().unwrap();
+        for ((i, j), &pixel) in tile.pixels().zip(pixels) {
+            framebuffer[(j * self.width + i) as usize] = pixel;
+        }
+    }
+
+    //pixels counted from the lower left corner
+    pub fn pixels(&self) -> Vec<PixelStats> {
+        self.pixels.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    #[test]
+    fn every_pixel_rendered_once() {
+        let (width, height) = (70, 40);
+        //worker 2 takes its own tiles and then steals the rest
+        let queue = TileQueue::new(tiles(width, height, 32), 3);
+        let framebuffer = Framebuffer::new(width, height);
+        let mut taken = 0;
+        while let Some(tile) = queue.pop(2) {
+            let mut pixels = framebuffer.read_tile(&tile);
+            for pixel in pixels.iter_mut() {
+                pixel.add(Color::WHITE);
+            }
+            framebuffer.write_tile(&tile, &pixels);
+            taken += 1;
+        }
+        assert!(queue.pop(0).is_none());
+
+        //six tiles that between them cover the whole image
+        assert_eq!(taken, 6);
+        assert!(framebuffer.pixels().iter().all(|pixel| pixel.count() == 1 && pixel.mean() == Color::WHITE));
+
+        //the next pass gets every tile again
+        queue.refill(tiles(width, height, 32));
+        assert_eq!(std::iter::from_fn(|| queue.pop(0)).count(), 6);
+    }
+}
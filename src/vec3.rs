@@ -1,5 +1,5 @@
 use std::ops;
-use raytracer::{clamp, random_f64, random_in_range};
+use raytracer::{clamp, random_f64, random_in_range, PI};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vec3(pub f64, pub f64, pub f64);
@@ -60,29 +60,41 @@ impl Vec3 {
         x*x + y*y + z*z
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        let mut p = Vec3::random_in_range(-1.0, 1.0);
-        while p.length_squared() >= 1.0 {
-            p = Vec3::random_in_range(-1.0, 1.0);
-        }
-        return p
+    pub fn random_unit_vector() -> Vec3 {
+        Vec3::sample_unit_vector((random_f64(), random_f64()))
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut p = Vec3::new(random_in_range(-1.0, 1.0), random_in_range(-1.0, 1.0), 0.0);
-        while p.length_squared() >= 1.0 {
-            p = Vec3::new(random_in_range(-1.0, 1.0), random_in_range(-1.0, 1.0), 0.0);
-        }
-        return p
+    //uniform direction from two numbers in [0, 1)
+    pub fn sample_unit_vector((u1, u2): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        Vec3(r * phi.cos(), r * phi.sin(), z)
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        unit_vector(Vec3::random_in_unit_sphere())
+    //uniform point in the unit ball from three numbers in [0, 1)
+    pub fn sample_in_unit_sphere(u: (f64, f64), u3: f64) -> Vec3 {
+        u3.cbrt() * Vec3::sample_unit_vector(u)
+    }
+
+    //uniform point in the unit disk in the xy plane from two numbers in [0, 1), using the concentric
+    //mapping of Shirley and Chiu, which keeps neighbouring samples close
+    pub fn sample_unit_disk((u1, u2): (f64, f64)) -> Vec3 {
+        let (x, y) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return Vec3::origin()
+        }
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, PI / 4.0 * (y / x))
+        } else {
+            (y, PI / 2.0 - PI / 4.0 * (x / y))
+        };
+        Vec3(r * theta.cos(), r * theta.sin(), 0.0)
     }
 
     pub fn near_zero(self) -> bool {
         let s = 1e-8;
-        (self.0 < s) && (self.1 < s) && (self.2 < s)
+        (self.0.abs() < s) && (self.1.abs() < s) && (self.2.abs() < s)
     }
 
 }
@@ -162,18 +174,22 @@ impl ops::Div<f64> for Vec3 {
     }
 }
 
+impl ops::Div<Vec3> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, rhs: Vec3) -> Vec3 {
+        Vec3(self.0 / rhs.0, self.1 / rhs.1, self.2 / rhs.2)
+    }
+}
+
 impl PartialEq for Vec3 {
     fn eq(&self, other: &Vec3) -> bool {
-        if self.0 == other.0 && self.1 == other.1 && self.2 == other.2 {
-            true
-        } else {
-            false
-        }
+        self.0 == other.0 && self.1 == other.1 && self.2 == other.2
     }
 }
 
 impl ops::AddAssign for Vec3 {
-    fn add_assign(&mut self, rhs: Self) -> () {
+    fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs;
     }
 }
@@ -275,6 +291,14 @@ mod tests {
         assert_eq!(result, Vec3(-0.5, -1.0, -1.5));
     }
 
+    #[test]
+    fn div_overload() {
+        let a = Vec3(1.0, 3.0, -4.0);
+        let b = Vec3(2.0, 1.5, 8.0);
+        let result = a / b;
+        assert_eq!(result, Vec3(0.5, 2.0, -0.5))
+    }
+
     #[test]
     fn add_assign() {
         let mut a = Vec3::origin();